@@ -0,0 +1,16 @@
+use answer::{Decoder, Emulator};
+use std::{env, fs};
+
+fn main() {
+    let decoder = match env::args().nth(1).as_deref() {
+        Some("v1") => Decoder::V1,
+        Some("v2") | None => Decoder::V2,
+        Some(other) => panic!("Unknown decoder version: {other}"),
+    };
+    let file = fs::read_to_string("./input.txt").unwrap();
+    let mut emulator = Emulator::new(decoder).with_trace();
+    emulator.run(&file);
+    emulator.trace().iter().for_each(|line| println!("{line}"));
+    println!("{}", emulator.dump());
+    println!("{}", emulator.sum());
+}
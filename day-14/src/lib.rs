@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display};
 
 use nom::{
     branch::alt,
@@ -9,36 +9,182 @@ use nom::{
 };
 
 pub fn process_part1(input: &str) -> String {
+    let mut emulator = Emulator::new(Decoder::V1);
+    emulator.run(input);
+    emulator.sum().to_string()
+}
+
+pub fn process_part2(input: &str) -> String {
+    let mut emulator = Emulator::new(Decoder::V2);
+    emulator.run(input);
+    emulator.sum().to_string()
+}
+
+pub fn process_part2_sparse(input: &str) -> String {
     let (_, instructions) = parse_input(input).unwrap();
     let mut mask = Instruction::Mask((0, 0, 0));
-    let mut memory = HashMap::new();
+    let mut memory = SparseMemory::default();
     for instruction in instructions {
         match instruction {
             Instruction::Mask(_) => mask = instruction,
             Instruction::Mem((addr, val)) => {
-                let val = mask.apply_val(val).unwrap();
-                memory.insert(addr, val);
+                memory.write(mask.apply_mem_pattern(addr).unwrap(), val);
             }
         }
     }
-    memory.values().sum::<u64>().to_string()
+    memory.sum().to_string()
 }
 
-pub fn process_part2(input: &str) -> String {
-    let (_, instructions) = parse_input(input).unwrap();
-    let mut mask = Instruction::Mask((0, 0, 0));
-    let mut memory = HashMap::new();
-    instructions
-        .into_iter()
-        .for_each(|instruction| match instruction {
-            Instruction::Mask(_) => mask = instruction,
-            Instruction::Mem((addr, val)) => {
-                mask.apply_mem(addr).unwrap().into_iter().for_each(|add| {
-                    memory.insert(add, val);
-                });
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoder {
+    V1,
+    V2,
+}
+
+#[derive(Debug)]
+pub struct Emulator {
+    decoder: Decoder,
+    mask: Instruction,
+    memory: HashMap<u64, u64>,
+    trace: Option<Vec<String>>,
+}
+
+impl Emulator {
+    pub fn new(decoder: Decoder) -> Self {
+        Self {
+            decoder,
+            mask: Instruction::Mask((0, u64::MAX, 0)),
+            memory: HashMap::new(),
+            trace: None,
+        }
+    }
+
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(Vec::new());
+        self
+    }
+
+    pub fn run(&mut self, input: &str) {
+        let (_, instructions) = parse_input(input).unwrap();
+        instructions
+            .into_iter()
+            .for_each(|instruction| self.step(instruction));
+    }
+
+    fn step(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Mask(_) => {
+                self.record(|| format!("{instruction}"));
+                self.mask = instruction;
             }
-        });
-    memory.values().sum::<u64>().to_string()
+            Instruction::Mem((addr, val)) => match self.decoder {
+                Decoder::V1 => {
+                    let masked = self.mask.apply_val(val).unwrap();
+                    self.memory.insert(addr, masked);
+                    self.record(|| format!("{instruction} -> wrote {masked} to {addr:#x}"));
+                }
+                Decoder::V2 => {
+                    let mut addresses = self.mask.apply_mem(addr).unwrap();
+                    addresses.iter().for_each(|&add| {
+                        self.memory.insert(add, val);
+                    });
+                    self.record(|| {
+                        addresses.sort();
+                        let addresses = addresses
+                            .into_iter()
+                            .map(|add| format!("{add:#x}"))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        format!("{instruction} -> wrote {val} to {addresses}")
+                    });
+                }
+            },
+        }
+    }
+
+    // only builds the line when tracing, it can be long for floating addresses
+    fn record(&mut self, line: impl FnOnce() -> String) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(line());
+        }
+    }
+
+    pub fn trace(&self) -> &[String] {
+        self.trace.as_deref().unwrap_or_default()
+    }
+
+    pub fn dump(&self) -> String {
+        let mut memory: Vec<_> = self.memory.iter().collect();
+        memory.sort();
+        memory
+            .into_iter()
+            .map(|(addr, val)| format!("{addr:#011x}: {val:#011x}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.memory.values().sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AddressPattern {
+    fixed: u64,
+    floating: u64,
+}
+
+impl AddressPattern {
+    fn len(&self) -> u64 {
+        1 << self.floating.count_ones()
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        (self.fixed ^ other.fixed) & !self.floating & !other.floating == 0
+    }
+
+    fn subtract(mut self, other: &Self) -> Vec<Self> {
+        if !self.overlaps(other) {
+            return vec![self];
+        }
+        let mut remainder = Vec::new();
+        let mut split = self.floating & !other.floating;
+        while split > 0 {
+            let bit = 1 << split.trailing_zeros();
+            self.floating &= !bit;
+            remainder.push(AddressPattern {
+                fixed: self.fixed | (!other.fixed & bit),
+                floating: self.floating,
+            });
+            self.fixed |= other.fixed & bit;
+            split &= !bit;
+        }
+        remainder
+    }
+}
+
+#[derive(Debug, Default)]
+struct SparseMemory {
+    writes: Vec<(AddressPattern, u64)>,
+}
+
+impl SparseMemory {
+    fn write(&mut self, pattern: AddressPattern, value: u64) {
+        self.writes = self
+            .writes
+            .drain(..)
+            .flat_map(|(p, v)| p.subtract(&pattern).into_iter().map(move |p| (p, v)))
+            .collect();
+        self.writes.push((pattern, value));
+    }
+
+    // 2^36 addresses holding 36-bit values can add up to more than a u64 holds
+    fn sum(&self) -> u128 {
+        self.writes
+            .iter()
+            .map(|(p, v)| p.len() as u128 * *v as u128)
+            .sum()
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -47,6 +193,25 @@ enum Instruction {
     Mem((u64, u64)),
 }
 
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            &Self::Mask((mask_on, _, mask_float)) => {
+                let mask: String = (0..36)
+                    .rev()
+                    .map(|i| match ((mask_float >> i) & 1, (mask_on >> i) & 1) {
+                        (1, _) => 'X',
+                        (_, 1) => '1',
+                        _ => '0',
+                    })
+                    .collect();
+                write!(f, "mask = {mask}")
+            }
+            Self::Mem((addr, val)) => write!(f, "mem[{addr}] = {val}"),
+        }
+    }
+}
+
 impl Instruction {
     fn apply_val(&self, value: u64) -> Result<u64, &str> {
         match self {
@@ -75,6 +240,16 @@ impl Instruction {
             Self::Mem(_) => Err("Attempted to apply Mem instruction to value!"),
         }
     }
+
+    fn apply_mem_pattern(&self, address: u64) -> Result<AddressPattern, &str> {
+        match self {
+            &Self::Mask((mask_on, _, mask_float)) => Ok(AddressPattern {
+                fixed: (address | mask_on) & !mask_float,
+                floating: mask_float,
+            }),
+            Self::Mem(_) => Err("Attempted to apply Mem instruction to value!"),
+        }
+    }
 }
 
 type Line = Instruction;
@@ -184,4 +359,81 @@ mem[26] = 1";
         let result = process_part2(input);
         assert_eq!(result, "208");
     }
+
+    #[test]
+    fn part2_sparse() {
+        let input = "mask = 000000000000000000000000000000X1001X
+mem[42] = 100
+mask = 00000000000000000000000000000000X0XX
+mem[26] = 1";
+        let result = process_part2_sparse(input);
+        assert_eq!(result, "208");
+    }
+
+    #[test]
+    fn sparse_matches_expansion() {
+        let input = "mask = 0000000000000000000000000000X1X0X01X
+mem[42] = 100
+mem[7] = 3
+mask = 00000000000000000000000000000000X0XX
+mem[26] = 1
+mask = 000000000000000000000000000000X1XX0X
+mem[18] = 9
+mem[26] = 5";
+        assert_eq!(process_part2_sparse(input), process_part2(input));
+    }
+
+    #[test]
+    fn sparse_many_floating_bits() {
+        let input = "mask = 0000XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[0] = 2
+mask = 00000000000000000000000000000000XXXX
+mem[0] = 1";
+        let result = process_part2_sparse(input);
+        assert_eq!(result, ((2_u64 << 32) - 16).to_string());
+    }
+
+    #[test]
+    fn sparse_sum_beyond_u64() {
+        let input = "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[0] = 68719476735";
+        let result = process_part2_sparse(input);
+        assert_eq!(result, ((1_u128 << 36) * 68719476735).to_string());
+    }
+
+    #[test]
+    fn emulator_trace() {
+        let input = "mask = 000000000000000000000000000000X1001X
+mem[42] = 100
+mask = 00000000000000000000000000000000X0XX
+mem[26] = 1";
+        let mut emulator = Emulator::new(Decoder::V2).with_trace();
+        emulator.run(input);
+        assert_eq!(
+            emulator.trace(),
+            [
+                "mask = 000000000000000000000000000000X1001X",
+                "mem[42] = 100 -> wrote 100 to 0x1a, 0x1b, 0x3a, 0x3b",
+                "mask = 00000000000000000000000000000000X0XX",
+                "mem[26] = 1 -> wrote 1 to 0x10, 0x11, 0x12, 0x13, 0x18, 0x19, 0x1a, 0x1b",
+            ]
+        );
+        assert_eq!(emulator.sum(), 208);
+    }
+
+    #[test]
+    fn emulator_dump() {
+        let input = "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X
+mem[8] = 11
+mem[7] = 101
+mem[8] = 0";
+        let mut emulator = Emulator::new(Decoder::V1);
+        emulator.run(input);
+        assert!(emulator.trace().is_empty());
+        assert_eq!(
+            emulator.dump(),
+            "0x000000007: 0x000000065
+0x000000008: 0x000000040"
+        );
+    }
 }
@@ -1,48 +1,184 @@
 use std::collections::HashMap;
 
-use nom::{bytes::complete::tag, character::complete::digit1, multi::separated_list1, IResult};
+use nom::{
+    bytes::complete::tag, character::complete::digit1, combinator::map_res, multi::separated_list1,
+    IResult,
+};
 
 pub fn process_part1(input: &str) -> String {
-    let (_, mut numbers) = parse_line(input).unwrap();
-    while numbers.len() < 2020 {
-        let last_number = *numbers.last().unwrap();
-        if let Some(new_number) = numbers.iter().rev().skip(1).position(|&n| n == last_number) {
-            numbers.push(new_number + 1);
+    let numbers = starting_numbers(input);
+    MemoryGame::with_turns(&numbers, 2020)
+        .nth(2020 - 1)
+        .unwrap()
+        .to_string()
+}
+
+pub fn process_part2(input: &str) -> String {
+    let numbers = starting_numbers(input);
+    MemoryGame::with_turns(&numbers, 30_000_000)
+        .nth(30_000_000 - 1)
+        .unwrap()
+        .to_string()
+}
+
+pub fn first_spoken(input: &str, number: usize, turns: usize) -> Option<usize> {
+    let numbers = starting_numbers(input);
+    MemoryGame::with_turns(&numbers, turns)
+        .take(turns)
+        .position(|n| n as usize == number)
+        .map(|i| i + 1)
+}
+
+pub fn last_spoken(input: &str, number: usize, turns: usize) -> Option<usize> {
+    let numbers = starting_numbers(input);
+    MemoryGame::with_turns(&numbers, turns)
+        .take(turns)
+        .enumerate()
+        .filter(|&(_, n)| n as usize == number)
+        .last()
+        .map(|(i, _)| i + 1)
+}
+
+pub fn distinct_spoken(input: &str, turns: usize) -> usize {
+    let numbers = starting_numbers(input);
+    let mut game = MemoryGame::with_turns(&numbers, turns);
+    game.by_ref().take(turns).for_each(drop);
+    game.distinct
+}
+
+pub fn to_csv(input: &str, turns: usize) -> String {
+    let numbers = starting_numbers(input);
+    let mut csv = String::from("turn,number\n");
+    MemoryGame::with_turns(&numbers, turns)
+        .take(turns)
+        .enumerate()
+        .for_each(|(i, n)| csv.push_str(&format!("{},{}\n", i + 1, n)));
+    csv
+}
+
+const FLAT_LIMIT: usize = 1 << 23;
+
+#[derive(Debug)]
+pub struct MemoryGame {
+    starting: Vec<u32>,
+    turn: u32,
+    next: u32,
+    distinct: usize,
+    flat_limit: usize,
+    recent: Vec<u32>,
+    seen: Vec<u64>,
+    sparse: HashMap<u32, u32>,
+}
+
+impl MemoryGame {
+    pub fn new(starting: &[u32]) -> Self {
+        Self::with_limit(starting, FLAT_LIMIT)
+    }
+
+    /// A game that will be played for `turns` turns. Apart from the starting
+    /// numbers nothing spoken can exceed the number of turns, so everything
+    /// but large starting numbers is kept in the flat table.
+    pub fn with_turns(starting: &[u32], turns: usize) -> Self {
+        Self::with_limit(starting, turns)
+    }
+
+    fn with_limit(starting: &[u32], flat_limit: usize) -> Self {
+        Self {
+            starting: starting.to_vec(),
+            turn: 0,
+            next: 0,
+            distinct: 0,
+            flat_limit,
+            recent: Vec::new(),
+            seen: Vec::new(),
+            sparse: HashMap::new(),
+        }
+    }
+
+    /// Records `number` as spoken on `turn` (1-based), returning how many turns
+    /// ago it was spoken before that, or 0 if it is new.
+    #[inline(always)]
+    fn speak(&mut self, number: u32, turn: u32) -> u32 {
+        let index = number as usize;
+        if index < self.flat_limit {
+            if index >= self.recent.len() {
+                let len = (index + 1).next_power_of_two().min(self.flat_limit);
+                self.recent.resize(len, 0);
+                self.seen.resize(len.div_ceil(64), 0);
+            }
+            // the bitset stays in cache while `recent` doesn't, so numbers spoken
+            // for the first time never wait on a load from the big table
+            let (word, bit) = (index / 64, 1 << (index % 64));
+            if self.seen[word] & bit == 0 {
+                self.seen[word] |= bit;
+                self.recent[index] = turn;
+                0
+            } else {
+                turn - std::mem::replace(&mut self.recent[index], turn)
+            }
         } else {
-            numbers.push(0);
+            self.speak_sparse(number, turn)
+        }
+    }
+
+    #[cold]
+    fn speak_sparse(&mut self, number: u32, turn: u32) -> u32 {
+        self.sparse
+            .insert(number, turn)
+            .map_or(0, |previous| turn - previous)
+    }
+}
+
+impl Iterator for MemoryGame {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let number = match self.starting.get(self.turn as usize) {
+            Some(&n) => n,
+            None => self.next,
+        };
+        self.turn = self.turn.checked_add(1)?;
+        self.next = self.speak(number, self.turn);
+        if self.next == 0 {
+            self.distinct += 1;
         }
+        Some(number)
+    }
+
+    // Skipping ahead is how the puzzle answers are found, so once the starting
+    // numbers are spoken the turns are played with the state kept in locals.
+    fn nth(&mut self, mut n: usize) -> Option<Self::Item> {
+        while n > 0 && (self.turn as usize) < self.starting.len() {
+            self.next()?;
+            n -= 1;
+        }
+        let (mut turn, mut next, mut distinct) = (self.turn, self.next, self.distinct);
+        for _ in 0..n {
+            turn = turn.checked_add(1)?;
+            next = self.speak(next, turn);
+            if next == 0 {
+                distinct += 1;
+            }
+        }
+        (self.turn, self.next, self.distinct) = (turn, next, distinct);
+        self.next()
     }
-    numbers.last().unwrap().to_string()
 }
 
-pub fn process_part2(input: &str) -> String {
-    let (_, numbers) = parse_line(input).unwrap();
-    let len = numbers.len();
-    let mut last = *numbers.last().unwrap();
-    let mut map: HashMap<usize, usize> = numbers
-        .into_iter()
-        .take(len - 1)
-        .enumerate()
-        .map(|(i, n)| (n, i))
-        .collect();
-    let mut turn = map.len();
-
-    while turn < 30_000_000 - 1 {
-        let previous = map.entry(last).or_insert(turn);
-        let distance = turn - *previous;
-        *previous = turn;
-        last = distance;
-        turn += 1;
-    }
-    last.to_string()
+fn starting_numbers(input: &str) -> Line {
+    let (rest, numbers) = parse_line(input.trim()).unwrap();
+    assert!(
+        rest.is_empty(),
+        "Can't read starting numbers from '{rest}', each has to fit in a u32"
+    );
+    numbers
 }
 
-type Line = Vec<usize>;
+// the game keeps numbers as u32, so larger starting numbers don't parse
+type Line = Vec<u32>;
 
 fn parse_line(input: &str) -> IResult<&str, Line> {
-    let (input, line) = separated_list1(tag(","), digit1)(input)?;
-    let line = line.into_iter().map(|s| s.parse().unwrap()).collect();
-    Ok((input, line))
+    separated_list1(tag(","), map_res(digit1, str::parse))(input)
 }
 
 #[cfg(test)]
@@ -68,6 +204,53 @@ mod tests {
         assert_eq!(process_part1(input), "1836");
     }
 
+    #[test]
+    fn sequence() {
+        let (_, numbers) = parse_line("0,3,6").unwrap();
+        let sequence: Vec<_> = MemoryGame::new(&numbers).take(10).collect();
+        assert_eq!(sequence, vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0]);
+        for (i, &number) in sequence.iter().enumerate() {
+            assert_eq!(MemoryGame::new(&numbers).nth(i), Some(number));
+        }
+        let mut game = MemoryGame::new(&numbers);
+        assert_eq!(game.nth(1), Some(3));
+        assert_eq!(game.nth(3), Some(3));
+        assert_eq!(game.next(), Some(1));
+    }
+
+    #[test]
+    fn large_starting_numbers() {
+        let (_, numbers) = parse_line("4294967295,0").unwrap();
+        let sequence: Vec<_> = MemoryGame::new(&numbers).take(4).collect();
+        assert_eq!(sequence, vec![u32::MAX, 0, 0, 1]);
+        let (_, numbers) = parse_line("20,7,0").unwrap();
+        let sequence: Vec<_> = MemoryGame::with_turns(&numbers, 10).take(10).collect();
+        assert_eq!(
+            sequence,
+            MemoryGame::new(&numbers).take(10).collect::<Vec<_>>()
+        );
+        assert_eq!(parse_line("0,4294967296"), Ok((",4294967296", vec![0])));
+    }
+
+    #[test]
+    #[should_panic(expected = "each has to fit in a u32")]
+    fn too_large_starting_number() {
+        process_part1("0,4294967296");
+    }
+
+    #[test]
+    fn analysis() {
+        let input = "0,3,6";
+        assert_eq!(first_spoken(input, 4, 10), Some(9));
+        assert_eq!(first_spoken(input, 4, 8), None);
+        assert_eq!(last_spoken(input, 0, 10), Some(10));
+        assert_eq!(last_spoken(input, 3, 10), Some(6));
+        assert_eq!(last_spoken(input, 2, 10), None);
+        assert_eq!(distinct_spoken(input, 10), 5);
+        assert_eq!(distinct_spoken(input, 2), 2);
+        assert_eq!(to_csv(input, 4), "turn,number\n1,0\n2,3\n3,6\n4,0\n");
+    }
+
     #[test]
     fn part2_1() {
         let input = "0,3,6";
@@ -1,7 +1,14 @@
-use answer::process_part1;
-use std::fs;
+use answer::{process_part1, scan_report, Format};
+use std::{env, fs};
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
     let file = fs::read_to_string("./input.txt").unwrap();
-    println!("{}", process_part1(&file));
+    if args.iter().any(|arg| arg == "--json") {
+        print!("{}", scan_report(&file, Format::Json));
+    } else if args.iter().any(|arg| arg == "--scan") {
+        print!("{}", scan_report(&file, Format::Table));
+    } else {
+        println!("{}", process_part1(&file));
+    }
 }
@@ -1,7 +1,25 @@
-use answer::process_part2;
-use std::fs;
+use answer::{process_part2_with, report};
+use std::{env, fs, process};
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
     let file = fs::read_to_string("./input.txt").unwrap();
-    println!("{}", process_part2(&file));
+    if args.iter().any(|arg| arg == "--report") {
+        println!("{}", report(&file));
+        return;
+    }
+    let prefix = match args.iter().position(|arg| arg == "--prefix") {
+        Some(i) => match args.get(i + 1) {
+            Some(prefix) => prefix.as_str(),
+            None => {
+                eprintln!("Missing value for --prefix\nusage: part-2 [--report] [--prefix PREFIX]");
+                process::exit(2);
+            }
+        },
+        None => "departure",
+    };
+    println!(
+        "{}",
+        process_part2_with(&file, |name| name.starts_with(prefix))
+    );
 }
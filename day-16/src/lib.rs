@@ -1,4 +1,4 @@
-use std::{collections::HashSet, ops::RangeInclusive};
+use std::{collections::HashSet, fmt::Display, ops::RangeInclusive};
 
 use nom::{
     bytes::complete::{tag, take_until},
@@ -11,7 +11,7 @@ pub fn process_part1(input: &str) -> String {
     let (_, (fields, _your_ticket, nearby_tickets)) = parse_input(input).unwrap();
     let all_ranges: Vec<RangeInclusive<usize>> = fields
         .iter()
-        .flat_map(|f| [f.ranges.0.clone(), f.ranges.1.clone()].into_iter())
+        .flat_map(|f| f.ranges.iter().cloned())
         .collect();
     let ticket_errors: Vec<usize> = nearby_tickets
         .into_iter()
@@ -22,36 +22,166 @@ pub fn process_part1(input: &str) -> String {
 }
 
 pub fn process_part2(input: &str) -> String {
-    let (_, (fields, your_ticket, nearby_tickets)) = parse_input(input).unwrap();
-    let valid_tickets = get_valid_tickets(&fields, nearby_tickets);
-    let det_ticket = determine_fields(your_ticket, valid_tickets, fields);
+    process_part2_with(input, |name| name.starts_with("departure"))
+}
+
+pub fn process_part2_with(input: &str, predicate: impl Fn(&str) -> bool) -> String {
+    let det_ticket = decode_your_ticket(input);
     let result = det_ticket
         .fields
         .into_iter()
-        .filter_map(|(name, value)| {
-            if name.starts_with("departure") {
-                Some(value)
-            } else {
-                None
-            }
-        })
+        .filter_map(|(name, value)| if predicate(name) { Some(value) } else { None })
         .product::<usize>();
     result.to_string()
 }
 
-fn get_valid_tickets(fields: &[Field], nearby_tickets: Vec<Ticket>) -> Vec<Ticket> {
-    let all_ranges: Vec<RangeInclusive<usize>> = fields
+pub fn report(input: &str) -> String {
+    decode_your_ticket(input)
+        .fields
+        .into_iter()
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn decode_your_ticket(input: &str) -> DeterminedTicket<'_> {
+    let (_, (fields, your_ticket, nearby_tickets)) = parse_input(input).unwrap();
+    let valid_tickets = get_valid_tickets(&fields, nearby_tickets);
+    determine_fields(your_ticket, valid_tickets, fields).unwrap_or_else(|e| panic!("{e}"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+pub fn scan_report(input: &str, format: Format) -> String {
+    let (_, (fields, _your_ticket, nearby_tickets)) = parse_input(input).unwrap();
+    let errors: Vec<ValueError> = nearby_tickets
         .iter()
-        .flat_map(|f| [f.ranges.0.clone(), f.ranges.1.clone()].into_iter())
+        .enumerate()
+        .flat_map(|(t, ticket)| {
+            let fields = &fields;
+            invalid_positions(fields, ticket).into_iter().map(move |i| {
+                let value = ticket.values[i];
+                let distance = fields.iter().map(|f| f.distance(value)).min().unwrap();
+                ValueError {
+                    ticket: t + 1,
+                    position: i + 1,
+                    value,
+                    distance,
+                    closest: fields
+                        .iter()
+                        .filter(|f| f.distance(value) == distance)
+                        .map(|f| f.name)
+                        .collect(),
+                }
+            })
+        })
         .collect();
+    match format {
+        Format::Table => {
+            let mut table = String::from("ticket\tposition\tvalue\tdistance\tclosest fields\n");
+            errors.iter().for_each(|e| {
+                table.push_str(&format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    e.ticket,
+                    e.position,
+                    e.value,
+                    e.distance,
+                    e.closest.join(", ")
+                ))
+            });
+            table
+        }
+        Format::Json => {
+            let mut tickets: Vec<String> = Vec::new();
+            errors
+                .chunk_by(|a, b| a.ticket == b.ticket)
+                .for_each(|chunk| {
+                    let values: Vec<String> = chunk
+                        .iter()
+                        .map(|e| {
+                            let closest: Vec<_> =
+                                e.closest.iter().map(|name| json_string(name)).collect();
+                            format!(
+                                "{{\"position\": {}, \"value\": {}, \"distance\": {}, \"closest\": [{}]}}",
+                                e.position,
+                                e.value,
+                                e.distance,
+                                closest.join(", ")
+                            )
+                        })
+                        .collect();
+                    tickets.push(format!(
+                        "  {{\"ticket\": {}, \"invalid\": [{}]}}",
+                        chunk[0].ticket,
+                        values.join(", ")
+                    ));
+                });
+            if tickets.is_empty() {
+                return "[]\n".to_string();
+            }
+            format!("[\n{}\n]\n", tickets.join(",\n"))
+        }
+    }
+}
+
+// tickets and positions are both counted from 1
+#[derive(Debug)]
+struct ValueError<'a> {
+    ticket: usize,
+    position: usize,
+    value: usize,
+    distance: usize,
+    closest: Vec<&'a str>,
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    s.chars().for_each(|c| match c {
+        '"' => out.push_str("\\\""),
+        '\\' => out.push_str("\\\\"),
+        c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+        c => out.push(c),
+    });
+    out.push('"');
+    out
+}
+
+// At most `limit` of them, a loosely constrained input can have factorially many.
+pub fn list_assignments(input: &str, limit: usize) -> String {
+    let (_, (fields, your_ticket, nearby_tickets)) = parse_input(input).unwrap();
+    let valid_tickets = get_valid_tickets(&fields, nearby_tickets);
+    let candidates = field_candidates(&fields, your_ticket.values.len(), &valid_tickets);
+    Assignments::new(&candidates)
+        .take(limit)
+        .map(|assignment| {
+            assignment
+                .into_iter()
+                .map(|f| fields[f].name)
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn get_valid_tickets(fields: &[Field], nearby_tickets: Vec<Ticket>) -> Vec<Ticket> {
     nearby_tickets
         .into_iter()
-        .filter(|ticket| {
-            ticket
-                .values
-                .iter()
-                .all(|val| !all_ranges.iter().all(|range| !range.contains(val)))
-        })
+        .filter(|ticket| invalid_positions(fields, ticket).is_empty())
+        .collect()
+}
+
+fn invalid_positions(fields: &[Field], ticket: &Ticket) -> Vec<usize> {
+    ticket
+        .values
+        .iter()
+        .enumerate()
+        .filter(|&(_, &val)| fields.iter().all(|f| !f.contains(val)))
+        .map(|(i, _)| i)
         .collect()
 }
 
@@ -59,53 +189,195 @@ fn determine_fields(
     your_ticket: Ticket,
     valid_tickets: Vec<Ticket>,
     fields: Vec<Field>,
-) -> DeterminedTicket {
+) -> Result<DeterminedTicket, FieldError> {
     let len = your_ticket.values.len();
-    let mut options = vec![HashSet::<_>::from_iter(fields.iter().cloned()); len];
-    valid_tickets.into_iter().for_each(|ticket| {
-        ticket.values.into_iter().enumerate().for_each(|(i, val)| {
-            options[i].retain(|f| f.contains(val));
-        })
-    });
-    let mut singles: HashSet<_> = options
-        .iter()
-        .filter_map(|s| {
-            if s.len() == 1 {
-                Some(s.iter().take(1).cloned())
-            } else {
-                None
-            }
+    let candidates = field_candidates(&fields, len, &valid_tickets);
+    let matching = match_fields(&candidates, fields.len(), None);
+    let named = |i: usize, fits: Vec<usize>| {
+        let names = fits.into_iter().map(|f| fields[f].name.to_string());
+        (i + 1, names.collect())
+    };
+    let unmatched: Vec<_> = (0..len)
+        .filter(|&i| matching[i].is_none())
+        .map(|i| named(i, candidates[i].clone()))
+        .collect();
+    if !unmatched.is_empty() {
+        return Err(FieldError::Unsatisfiable(unmatched));
+    }
+    // a position is ambiguous if some other perfect matching avoids its field
+    let ambiguous: Vec<_> = (0..len)
+        .filter(|&i| {
+            let banned = (i, matching[i].unwrap());
+            match_fields(&candidates, fields.len(), Some(banned))
+                .iter()
+                .all(Option::is_some)
         })
-        .flatten()
         .collect();
-
-    while singles.len() < len {
-        options.iter_mut().for_each(|s| {
-            if s.len() > 1 {
-                s.retain(|f| !singles.contains(f));
-            }
-        });
-        singles = options
-            .iter()
-            .filter_map(|s| {
-                if s.len() == 1 {
-                    Some(s.iter().take(1).cloned())
-                } else {
-                    None
-                }
+    if !ambiguous.is_empty() {
+        // only name the fields some complete assignment gives the position
+        let ambiguous = ambiguous
+            .into_iter()
+            .map(|i| {
+                let fits = candidates[i]
+                    .iter()
+                    .copied()
+                    .filter(|&f| {
+                        let mut fixed = candidates.clone();
+                        fixed[i] = vec![f];
+                        match_fields(&fixed, fields.len(), None)
+                            .iter()
+                            .all(Option::is_some)
+                    })
+                    .collect();
+                named(i, fits)
             })
-            .flatten()
             .collect();
+        return Err(FieldError::Ambiguous(ambiguous));
     }
 
-    let determined_fields = options
+    let determined_fields = matching
         .into_iter()
-        .map(|s| s.into_iter().last().unwrap().name)
-        .zip(your_ticket.values.into_iter())
+        .map(|f| fields[f.unwrap()].name)
+        .zip(your_ticket.values)
         .collect();
 
-    DeterminedTicket {
+    Ok(DeterminedTicket {
         fields: determined_fields,
+    })
+}
+
+fn field_candidates(fields: &[Field], len: usize, valid_tickets: &[Ticket]) -> Vec<Vec<usize>> {
+    (0..len)
+        .map(|i| {
+            (0..fields.len())
+                .filter(|&f| {
+                    valid_tickets
+                        .iter()
+                        .all(|ticket| fields[f].contains(ticket.values[i]))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Maximum bipartite matching of positions to fields (Kuhn's augmenting paths),
+// optionally ignoring one (position, field) edge.
+fn match_fields(
+    candidates: &[Vec<usize>],
+    field_count: usize,
+    banned: Option<(usize, usize)>,
+) -> Vec<Option<usize>> {
+    fn augment(
+        i: usize,
+        candidates: &[Vec<usize>],
+        banned: Option<(usize, usize)>,
+        seen: &mut [bool],
+        owner: &mut [Option<usize>],
+    ) -> bool {
+        for &f in &candidates[i] {
+            if banned == Some((i, f)) || seen[f] {
+                continue;
+            }
+            seen[f] = true;
+            if owner[f].is_none_or(|j| augment(j, candidates, banned, seen, owner)) {
+                owner[f] = Some(i);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut owner = vec![None; field_count];
+    for i in 0..candidates.len() {
+        augment(
+            i,
+            candidates,
+            banned,
+            &mut vec![false; field_count],
+            &mut owner,
+        );
+    }
+    let mut matching = vec![None; candidates.len()];
+    owner
+        .into_iter()
+        .enumerate()
+        .for_each(|(f, i)| i.into_iter().for_each(|i| matching[i] = Some(f)));
+    matching
+}
+
+// Every way to give each position its own field, found depth first one at a
+// time. `stack` holds the index into `candidates` chosen for each position so
+// far, and `resume` where to carry on at the next position.
+struct Assignments<'a> {
+    candidates: &'a [Vec<usize>],
+    stack: Vec<usize>,
+    used: HashSet<usize>,
+    resume: Option<usize>,
+}
+
+impl<'a> Assignments<'a> {
+    fn new(candidates: &'a [Vec<usize>]) -> Self {
+        Self {
+            candidates,
+            stack: Vec::new(),
+            used: HashSet::new(),
+            resume: Some(0),
+        }
+    }
+
+    fn backtrack(&mut self) -> Option<usize> {
+        let k = self.stack.pop()?;
+        self.used.remove(&self.candidates[self.stack.len()][k]);
+        Some(k + 1)
+    }
+}
+
+impl Iterator for Assignments<'_> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let from = self.resume?;
+            let i = self.stack.len();
+            if i == self.candidates.len() {
+                let assignment = (0..i).map(|p| self.candidates[p][self.stack[p]]).collect();
+                self.resume = self.backtrack();
+                return Some(assignment);
+            }
+            let options = &self.candidates[i];
+            match (from..options.len()).find(|&k| !self.used.contains(&options[k])) {
+                Some(k) => {
+                    self.used.insert(options[k]);
+                    self.stack.push(k);
+                    self.resume = Some(0);
+                }
+                None => self.resume = self.backtrack(),
+            }
+        }
+    }
+}
+
+/// Positions (1-based, like the reports) with the fields that fit them.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FieldError {
+    Unsatisfiable(Vec<(usize, Vec<String>)>),
+    Ambiguous(Vec<(usize, Vec<String>)>),
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (message, positions) = match self {
+            Self::Unsatisfiable(positions) => ("No field left for", positions),
+            Self::Ambiguous(positions) => ("More than one field fits", positions),
+        };
+        let positions: Vec<String> = positions
+            .iter()
+            .map(|(position, fields)| match fields.len() {
+                0 => format!("position {position} (no field fits)"),
+                _ => format!("position {position} ({})", fields.join(" or ")),
+            })
+            .collect();
+        write!(f, "{message} {}", positions.join(", "))
     }
 }
 
@@ -117,12 +389,26 @@ struct Ticket {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Field<'a> {
     name: &'a str,
-    ranges: (RangeInclusive<usize>, RangeInclusive<usize>),
+    ranges: Vec<RangeInclusive<usize>>,
 }
 
 impl<'a> Field<'a> {
     fn contains(&self, n: usize) -> bool {
-        self.ranges.0.contains(&n) || self.ranges.1.contains(&n)
+        self.ranges.iter().any(|range| range.contains(&n))
+    }
+
+    fn distance(&self, n: usize) -> usize {
+        self.ranges
+            .iter()
+            .map(|range| {
+                if n < *range.start() {
+                    range.start() - n
+                } else {
+                    n.saturating_sub(*range.end())
+                }
+            })
+            .min()
+            .unwrap_or(usize::MAX)
     }
 }
 
@@ -131,7 +417,7 @@ struct DeterminedTicket<'a> {
     fields: Vec<(&'a str, usize)>,
 }
 
-fn parse_input(input: &str) -> IResult<&str, (Vec<Field>, Ticket, Vec<Ticket>)> {
+fn parse_input(input: &str) -> IResult<&str, (Vec<Field<'_>>, Ticket, Vec<Ticket>)> {
     let (input, fields_input) = take_until("\n\n")(input)?;
     let (_, fields) = separated_list1(tag("\n"), parse_field)(fields_input)?;
     let (input, _) = tag("\n\nyour ticket:\n")(input)?;
@@ -141,19 +427,11 @@ fn parse_input(input: &str) -> IResult<&str, (Vec<Field>, Ticket, Vec<Ticket>)>
     Ok((input, (fields, your_ticket, nearby_tickets)))
 }
 
-fn parse_field(input: &str) -> IResult<&str, Field> {
+fn parse_field(input: &str) -> IResult<&str, Field<'_>> {
     let (input, name) = take_until(": ")(input)?;
     let (input, _) = tag(": ")(input)?;
-    let (input, range1) = parse_range(input)?;
-    let (input, _) = tag(" or ")(input)?;
-    let (input, range2) = parse_range(input)?;
-    Ok((
-        input,
-        Field {
-            name,
-            ranges: (range1, range2),
-        },
-    ))
+    let (input, ranges) = separated_list1(tag(" or "), parse_range)(input)?;
+    Ok((input, Field { name, ranges }))
 }
 
 fn parse_range(input: &str) -> IResult<&str, RangeInclusive<usize>> {
@@ -193,6 +471,40 @@ nearby tickets:
         assert_eq!(result, "71");
     }
 
+    #[test]
+    fn part1_report() {
+        let input = "class: 1-3 or 5-7
+row: 6-11 or 33-44
+seat: 13-40 or 45-50
+
+your ticket:
+7,1,14
+
+nearby tickets:
+7,3,47
+40,4,50
+55,2,20
+38,6,12";
+        assert_eq!(
+            scan_report(input, Format::Table),
+            "ticket\tposition\tvalue\tdistance\tclosest fields
+2\t2\t4\t1\tclass
+3\t1\t55\t5\tseat
+4\t3\t12\t1\trow, seat
+"
+        );
+        assert_eq!(
+            scan_report(input, Format::Json),
+            r#"[
+  {"ticket": 2, "invalid": [{"position": 2, "value": 4, "distance": 1, "closest": ["class"]}]},
+  {"ticket": 3, "invalid": [{"position": 1, "value": 55, "distance": 5, "closest": ["seat"]}]},
+  {"ticket": 4, "invalid": [{"position": 3, "value": 12, "distance": 1, "closest": ["row", "seat"]}]}
+]
+"#
+        );
+        assert_eq!(json_string("a \"b\"\\"), r#""a \"b\"\\""#);
+    }
+
     #[test]
     fn part2_valid() {
         let input = "class: 1-3 or 5-7
@@ -235,9 +547,97 @@ nearby tickets:
         let det_ticket = determine_fields(your_ticket, valid_tickets, fields);
         assert_eq!(
             det_ticket,
-            DeterminedTicket {
+            Ok(DeterminedTicket {
                 fields: vec![("row", 11), ("class", 12), ("seat", 13)]
-            }
+            })
+        );
+        assert_eq!(list_assignments(input, 10), "row,class,seat");
+    }
+
+    #[test]
+    fn part2_custom() {
+        let input = "class: 0-1 or 4-19
+row: 0-5 or 8-19
+seat: 0-13 or 16-19 or 30-40
+note: 100-200
+
+your ticket:
+11,12,13,150
+
+nearby tickets:
+3,9,18,101
+15,1,5,102
+5,14,35,199";
+        assert_eq!(
+            process_part2_with(input, |name| name.starts_with('s')),
+            "13"
+        );
+        assert_eq!(process_part2_with(input, |name| name.len() == 4), "1950");
+        assert_eq!(report(input), "row: 11\nclass: 12\nseat: 13\nnote: 150");
+    }
+
+    #[test]
+    fn part2_ambiguous() {
+        let input = "class: 0-1 or 4-19
+row: 0-5 or 8-19
+seat: 0-13 or 16-19
+
+your ticket:
+11,12,13
+
+nearby tickets:
+3,9,18
+15,1,5";
+        let (_, (fields, your_ticket, nearby_tickets)) = parse_input(input).unwrap();
+        let valid_tickets = get_valid_tickets(&fields, nearby_tickets);
+        let det_ticket = determine_fields(your_ticket, valid_tickets, fields);
+        let error = det_ticket.unwrap_err();
+        let fits = vec!["class".to_string(), "seat".to_string()];
+        assert_eq!(
+            error,
+            FieldError::Ambiguous(vec![(2, fits.clone()), (3, fits)])
+        );
+        assert_eq!(
+            error.to_string(),
+            "More than one field fits position 2 (class or seat), position 3 (class or seat)"
+        );
+        assert_eq!(
+            list_assignments(input, 10),
+            "row,class,seat\nrow,seat,class"
+        );
+        assert_eq!(list_assignments(input, 1), "row,class,seat");
+    }
+
+    #[test]
+    fn part2_unsatisfiable() {
+        let input = "class: 0-1 or 4-19
+row: 0-1 or 4-19
+seat: 0-13 or 16-19
+
+your ticket:
+11,12,13
+
+nearby tickets:
+3,9,18
+15,1,5";
+        let (_, (fields, your_ticket, nearby_tickets)) = parse_input(input).unwrap();
+        let valid_tickets = get_valid_tickets(&fields, nearby_tickets);
+        let det_ticket = determine_fields(your_ticket, valid_tickets, fields);
+        assert!(matches!(det_ticket, Err(FieldError::Unsatisfiable(_))));
+        assert_eq!(list_assignments(input, 10), "");
+    }
+
+    #[test]
+    fn many_assignments() {
+        let fields: Vec<String> = (0..20).map(|i| format!("f{i}: 0-100")).collect();
+        let values = vec!["1"; 20].join(",");
+        let input = format!(
+            "{}\n\nyour ticket:\n{values}\n\nnearby tickets:\n{values}",
+            fields.join("\n")
         );
+        assert_eq!(list_assignments(&input, 3).lines().count(), 3);
+        let candidates: Vec<Vec<usize>> = vec![(0..3).collect(); 3];
+        assert_eq!(Assignments::new(&candidates).count(), 6);
+        assert_eq!(Assignments::new(&[]).count(), 1);
     }
 }
@@ -0,0 +1,19 @@
+use answer::{parenthesise, trace, Precedence};
+use std::{env, fs};
+
+fn main() {
+    let precedence = match env::args().nth(1).as_deref() {
+        Some("1") => Precedence::equal(),
+        Some("2") | None => Precedence::addition_first(),
+        Some(other) => panic!("Unknown part: {other}"),
+    };
+    let file = fs::read_to_string("./input.txt").unwrap();
+    for line in file.lines().filter(|line| !line.trim().is_empty()) {
+        let parenthesised =
+            parenthesise(line, &precedence).unwrap_or_else(|e| panic!("{line}: {e}"));
+        let steps = trace(line, &precedence).unwrap_or_else(|e| panic!("{line}: {e}"));
+        println!("{parenthesised}");
+        println!("{}", steps.join("\n→ "));
+        println!();
+    }
+}
@@ -1,140 +1,383 @@
-use meval::Expr;
+use std::{collections::HashMap, fmt::Display};
 
 use nom::{
     branch::alt,
-    character::complete::{digit1, newline, one_of, space0},
-    combinator::map_res,
-    multi::{many1, separated_list1},
+    character::complete::{digit1, one_of, space0},
+    combinator::{map, map_res},
+    multi::many0,
     sequence::delimited,
     IResult,
 };
 
 pub fn process_part1(input: &str) -> String {
-    let (_, equations) = parse_input(input).unwrap();
-    equations
-        .into_iter()
-        .map(|e| e.eval())
-        .sum::<isize>()
-        .to_string()
+    process(input, &Precedence::equal()).unwrap_or_else(|e| panic!("{e}"))
 }
 
 pub fn process_part2(input: &str) -> String {
-    let (_, exprs) = parse_input2(input).unwrap();
-    exprs
-        .into_iter()
-        .map(|e| e.eval().unwrap() as isize)
-        .sum::<isize>()
-        .to_string()
+    process(input, &Precedence::addition_first()).unwrap_or_else(|e| panic!("{e}"))
+}
+
+pub fn process(input: &str, precedence: &Precedence) -> Result<String, Error> {
+    input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .try_fold(0_isize, |sum, line| {
+            sum.checked_add(evaluate(line, precedence)?)
+                .ok_or(Error::Overflow)
+        })
+        .map(|sum| sum.to_string())
+}
+
+pub fn evaluate(line: &str, precedence: &Precedence) -> Result<isize, Error> {
+    parse_line(line, precedence)?.eval()
+}
+
+pub fn parenthesise(line: &str, precedence: &Precedence) -> Result<String, Error> {
+    Ok(parse_line(line, precedence)?.fully_parenthesised())
+}
+
+pub fn trace(line: &str, precedence: &Precedence) -> Result<Vec<String>, Error> {
+    let mut equation = parse_line(line, precedence)?;
+    let mut steps = vec![equation.to_string()];
+    while let Some(level) = equation.reducible_level(precedence) {
+        equation.reduce(level, precedence)?;
+        // negating a literal changes the tree but not how it is written
+        let step = equation.to_string();
+        if steps.last() != Some(&step) {
+            steps.push(step);
+        }
+    }
+    Ok(steps)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Parse(String),
+    UnknownOperator(char),
+    Overflow,
+    DivideByZero,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "Can't parse expression: {message}"),
+            Error::UnknownOperator(op) => write!(f, "Unknown operator '{op}'"),
+            Error::Overflow => write!(f, "Result doesn't fit in an isize"),
+            Error::DivideByZero => write!(f, "Division by zero"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+pub struct Precedence {
+    levels: HashMap<char, (u8, Associativity)>,
+    prefix: HashMap<char, u8>,
+}
+
+impl Precedence {
+    pub fn new(levels: &[(char, u8)]) -> Self {
+        Self {
+            levels: levels
+                .iter()
+                .map(|&(op, level)| (op, (level, Associativity::Left)))
+                .collect(),
+            prefix: HashMap::new(),
+        }
+    }
+
+    pub fn equal() -> Self {
+        Self::new(&[('+', 1), ('*', 1)])
+    }
+
+    pub fn addition_first() -> Self {
+        Self::new(&[('+', 2), ('*', 1)])
+    }
+
+    pub fn with_operator(mut self, op: char, level: u8, associativity: Associativity) -> Self {
+        self.levels.insert(op, (level, associativity));
+        self
+    }
+
+    pub fn with_prefix(mut self, op: char, level: u8) -> Self {
+        self.prefix.insert(op, level);
+        self
+    }
+
+    fn level(&self, op: char) -> Option<u8> {
+        self.levels.get(&op).map(|&(level, _)| level)
+    }
+
+    // powers are twice the level, so they need more room than any `u8` level
+    fn binding_power(&self, op: char) -> Result<(u16, u16), Error> {
+        let &(level, associativity) = self.levels.get(&op).ok_or(Error::UnknownOperator(op))?;
+        let power = 2 * u16::from(level);
+        match associativity {
+            Associativity::Left => Ok((power, power + 1)),
+            Associativity::Right => Ok((power + 1, power)),
+        }
+    }
+
+    fn prefix_binding_power(&self, op: char) -> Result<u16, Error> {
+        let &level = self.prefix.get(&op).ok_or(Error::UnknownOperator(op))?;
+        Ok(2 * u16::from(level) + 1)
+    }
 }
 
 #[derive(Debug, Clone)]
 enum Equation {
     Mul(Box<Equation>, Box<Equation>),
     Add(Box<Equation>, Box<Equation>),
+    Sub(Box<Equation>, Box<Equation>),
+    Div(Box<Equation>, Box<Equation>),
+    Rem(Box<Equation>, Box<Equation>),
+    Neg(Box<Equation>),
+    Group(Box<Equation>),
     Int(isize),
 }
 
 impl Equation {
-    fn new(op: char, left: Self, right: Self) -> Self {
+    fn new(op: char, left: Self, right: Self) -> Result<Self, Error> {
+        let (left, right) = (Box::from(left), Box::from(right));
+        match op {
+            '+' => Ok(Self::Add(left, right)),
+            '*' => Ok(Self::Mul(left, right)),
+            '-' => Ok(Self::Sub(left, right)),
+            '/' => Ok(Self::Div(left, right)),
+            '%' => Ok(Self::Rem(left, right)),
+            _ => Err(Error::UnknownOperator(op)),
+        }
+    }
+
+    fn new_prefix(op: char, operand: Self) -> Result<Self, Error> {
         match op {
-            '+' => Self::Add(Box::from(left), Box::from(right)),
-            '*' => Self::Mul(Box::from(left), Box::from(right)),
-            _ => unimplemented!(),
+            '-' => Ok(Self::Neg(Box::from(operand))),
+            _ => Err(Error::UnknownOperator(op)),
+        }
+    }
+
+    fn operands(&self) -> Option<(char, &Self, &Self)> {
+        match self {
+            Self::Mul(a, b) => Some(('*', a, b)),
+            Self::Add(a, b) => Some(('+', a, b)),
+            Self::Sub(a, b) => Some(('-', a, b)),
+            Self::Div(a, b) => Some(('/', a, b)),
+            Self::Rem(a, b) => Some(('%', a, b)),
+            Self::Neg(_) | Self::Group(_) | Self::Int(_) => None,
+        }
+    }
+
+    fn eval(&self) -> Result<isize, Error> {
+        match self {
+            &Self::Int(x) => Ok(x),
+            Self::Group(e) => e.eval(),
+            Self::Neg(e) => e.eval()?.checked_neg().ok_or(Error::Overflow),
+            _ => {
+                let (op, a, b) = self.operands().unwrap();
+                apply(op, a.eval()?, b.eval()?)
+            }
+        }
+    }
+
+    fn value(&self) -> Option<isize> {
+        match self {
+            &Self::Int(x) => Some(x),
+            Self::Group(e) => e.value(),
+            _ => None,
         }
     }
 
-    fn eval(&self) -> isize {
+    fn fully_parenthesised(&self) -> String {
         match self {
-            &Self::Int(x) => x,
-            Self::Mul(a, b) => a.eval() * b.eval(),
-            Self::Add(a, b) => a.eval() + b.eval(),
-        }
-    }
-}
-
-// replace + )+(
-// replace * ))*((
-// add (( at the beginning of each expression and after each left parenthesis in the original expression; and
-// add )) at the end of the expression and before each right parenthesis in the original expression.
-
-fn parenthesise_input(input: &str) -> String {
-    // dbg!(input);
-    let mut result = String::new();
-    result.push_str("((");
-    input.chars().for_each(|c| match c {
-        '+' => result.push_str(") + ("),
-        '*' => result.push_str(")) * (("),
-        '(' => result.push_str("((("),
-        ')' => result.push_str(")))"),
-        ' ' => {}
-        other => result.push(other),
-    });
-    result.push_str("))");
-    assert_eq!(
-        result.chars().filter(|&c| c == '(').count(),
-        result.chars().filter(|&c| c == ')').count()
-    );
-    result
-}
-
-fn parse_input2(input: &str) -> IResult<&str, Vec<Expr>> {
-    let (input, lines) = separated_list1(newline, parse_expr)(input)?;
-    Ok((input, lines))
-}
-
-fn parse_expr(input: &str) -> IResult<&str, Expr> {
-    let (input, expr) = many1(one_of("0123456789+*() "))(input)?;
-    let expr_str: String = expr.into_iter().collect();
-    let par_expr = parenthesise_input(&expr_str).parse().unwrap();
-    Ok((input, par_expr))
-}
-
-fn parse_input(input: &str) -> IResult<&str, Vec<Equation>> {
-    let (input, lines) = separated_list1(newline, parse_equation)(input)?;
-    Ok((input, lines))
-}
-
-fn parse_equation(input: &str) -> IResult<&str, Equation> {
-    // println!("Parsing equation:{}", input);
-    let (input, mut lhs) = parse_term(input)?;
-    // println!("About to break?");
-    let (input, mut op) = one_of("+*")(input)?;
-    let (input, _) = space0(input)?;
-    let (mut input, mut rhs) = parse_term(input)?;
-    while !input.is_empty() && !input.starts_with(')') && !input.starts_with('\n') {
-        lhs = Equation::new(op, lhs.clone(), rhs.clone());
-        (input, op) = one_of("+*")(input)?;
-        (input, _) = space0(input)?;
-        (input, rhs) = parse_term(input)?;
-    }
-    let eq = Equation::new(op, lhs, rhs);
-    // println!("Parsed equation sucessfully! '{:?}' input:{}", eq, input);
-    Ok((input, eq))
-}
-
-fn parse_term(input: &str) -> IResult<&str, Equation> {
-    // println!("Parsing term:{}", input);
-    alt((parse_int, parse_parens))(input)
-}
-
-fn parse_int(input: &str) -> IResult<&str, Equation> {
-    // println!("Parsing int:{}", input);
-    let (input, n) = map_res(digit1, |digits: &str| digits.parse())(input)?;
-    let (input, _) = space0(input)?;
-    // println!("Parsed int successfuly! '{}' input:{}", n, input);
-    Ok((input, Equation::Int(n)))
-}
-
-fn parse_parens(input: &str) -> IResult<&str, Equation> {
-    // println!("Parsing parens:{}", input);
-    let (input, eq) = delimited(
-        nom::character::complete::char('('),
-        alt((parse_equation, parse_term)),
-        nom::character::complete::char(')'),
-    )(input)?;
-    let (input, _) = space0(input)?;
-    // println!("Parsed parens successfuly! '{:?}' input:{}", eq, input);
-    Ok((input, eq))
+            Self::Int(x) => x.to_string(),
+            Self::Group(e) => e.fully_parenthesised(),
+            Self::Neg(e) => format!("(-{})", e.fully_parenthesised()),
+            _ => {
+                let (op, a, b) = self.operands().unwrap();
+                format!(
+                    "({} {op} {})",
+                    a.fully_parenthesised(),
+                    b.fully_parenthesised()
+                )
+            }
+        }
+    }
+
+    // An operation can be reduced once all of its operands are plain numbers;
+    // the highest precedence level goes first, ties are broken left to right.
+    fn reducible_level(&self, precedence: &Precedence) -> Option<u8> {
+        match self {
+            Self::Int(_) => None,
+            Self::Group(e) => e.reducible_level(precedence),
+            Self::Neg(e) => match e.value() {
+                Some(_) => precedence.prefix.get(&'-').copied(),
+                None => e.reducible_level(precedence),
+            },
+            _ => {
+                let (op, a, b) = self.operands().unwrap();
+                if a.value().is_some() && b.value().is_some() {
+                    precedence.level(op)
+                } else {
+                    a.reducible_level(precedence)
+                        .max(b.reducible_level(precedence))
+                }
+            }
+        }
+    }
+
+    fn reduce(&mut self, level: u8, precedence: &Precedence) -> Result<bool, Error> {
+        let reduced = match self {
+            Self::Int(_) => return Ok(false),
+            Self::Group(e) => e.reduce(level, precedence)?,
+            Self::Neg(e) => {
+                if e.reduce(level, precedence)? {
+                    true
+                } else {
+                    match e.value() {
+                        Some(x) if precedence.prefix.get(&'-') == Some(&level) => {
+                            *self = Self::Int(x.checked_neg().ok_or(Error::Overflow)?);
+                            true
+                        }
+                        _ => false,
+                    }
+                }
+            }
+            Self::Mul(a, b)
+            | Self::Add(a, b)
+            | Self::Sub(a, b)
+            | Self::Div(a, b)
+            | Self::Rem(a, b) => {
+                if a.reduce(level, precedence)? || b.reduce(level, precedence)? {
+                    true
+                } else {
+                    let (op, a, b) = self.operands().unwrap();
+                    match (a.value(), b.value()) {
+                        (Some(x), Some(y)) if precedence.level(op) == Some(level) => {
+                            *self = Self::Int(apply(op, x, y)?);
+                            true
+                        }
+                        _ => false,
+                    }
+                }
+            }
+        };
+        if let Self::Group(e) = self {
+            if let &Self::Int(x) = e.as_ref() {
+                *self = Self::Int(x);
+            }
+        }
+        Ok(reduced)
+    }
+}
+
+impl Display for Equation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int(x) => write!(f, "{x}"),
+            Self::Group(e) => write!(f, "({e})"),
+            Self::Neg(e) => write!(f, "-{e}"),
+            _ => {
+                let (op, a, b) = self.operands().unwrap();
+                write!(f, "{a} {op} {b}")
+            }
+        }
+    }
+}
+
+fn apply(op: char, a: isize, b: isize) -> Result<isize, Error> {
+    match op {
+        '+' => a.checked_add(b).ok_or(Error::Overflow),
+        '*' => a.checked_mul(b).ok_or(Error::Overflow),
+        '-' => a.checked_sub(b).ok_or(Error::Overflow),
+        '/' | '%' if b == 0 => Err(Error::DivideByZero),
+        '/' => a.checked_div(b).ok_or(Error::Overflow),
+        '%' => a.checked_rem(b).ok_or(Error::Overflow),
+        _ => Err(Error::UnknownOperator(op)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Int(isize),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn parse_line(line: &str, precedence: &Precedence) -> Result<Equation, Error> {
+    let (rest, tokens) = tokenise(line).map_err(|e| Error::Parse(e.to_string()))?;
+    if !rest.is_empty() {
+        return Err(Error::Parse(format!("unexpected input '{rest}'")));
+    }
+    let mut tokens = tokens.into_iter().peekable();
+    let equation = parse_equation(&mut tokens, precedence, 0)?;
+    match tokens.next() {
+        None => Ok(equation),
+        Some(token) => Err(Error::Parse(format!("unexpected token {token:?}"))),
+    }
+}
+
+type Tokens = std::iter::Peekable<std::vec::IntoIter<Token>>;
+
+// Pratt parser: an operator only binds to the left-hand side if its binding
+// power is at least `min_power`, so higher levels end up deeper in the tree.
+fn parse_equation(
+    tokens: &mut Tokens,
+    precedence: &Precedence,
+    min_power: u16,
+) -> Result<Equation, Error> {
+    let mut lhs = parse_term(tokens, precedence)?;
+    while let Some(&Token::Op(op)) = tokens.peek() {
+        let (left_power, right_power) = precedence.binding_power(op)?;
+        if left_power < min_power {
+            break;
+        }
+        tokens.next();
+        let rhs = parse_equation(tokens, precedence, right_power)?;
+        lhs = Equation::new(op, lhs, rhs)?;
+    }
+    Ok(lhs)
+}
+
+fn parse_term(tokens: &mut Tokens, precedence: &Precedence) -> Result<Equation, Error> {
+    match tokens.next() {
+        Some(Token::Int(n)) => Ok(Equation::Int(n)),
+        Some(Token::Op(op)) => {
+            let power = precedence.prefix_binding_power(op)?;
+            let operand = parse_equation(tokens, precedence, power)?;
+            Equation::new_prefix(op, operand)
+        }
+        Some(Token::Open) => {
+            let equation = parse_equation(tokens, precedence, 0)?;
+            match tokens.next() {
+                Some(Token::Close) => Ok(Equation::Group(Box::from(equation))),
+                other => Err(Error::Parse(format!("expected ')', found {other:?}"))),
+            }
+        }
+        other => Err(Error::Parse(format!("expected term, found {other:?}"))),
+    }
+}
+
+fn tokenise(input: &str) -> IResult<&str, Vec<Token>> {
+    let (input, tokens) = many0(delimited(space0, parse_token, space0))(input)?;
+    Ok((input, tokens))
+}
+
+fn parse_token(input: &str) -> IResult<&str, Token> {
+    alt((
+        map_res(digit1, |digits: &str| digits.parse().map(Token::Int)),
+        map(one_of("()"), |c| match c {
+            '(' => Token::Open,
+            _ => Token::Close,
+        }),
+        map(one_of("+-*/%"), Token::Op),
+    ))(input)
 }
 
 #[cfg(test)]
@@ -183,6 +426,155 @@ mod tests {
         assert_eq!(result, "13632");
     }
 
+    #[test]
+    fn custom_precedence() {
+        let precedence = Precedence::new(&[('+', 1), ('*', 2)]);
+        assert_eq!(evaluate("1 + 2 * 3 + 4 * 5 + 6", &precedence), Ok(33));
+        assert_eq!(evaluate("2 * 3 + (4 * 5)", &precedence), Ok(26));
+        let precedence = Precedence::new(&[('+', 1)]);
+        assert_eq!(
+            evaluate("2 * 3", &precedence),
+            Err(Error::UnknownOperator('*'))
+        );
+    }
+
+    #[test]
+    fn exact_integers() {
+        let precedence = Precedence::equal();
+        assert_eq!(
+            evaluate("9007199254740993 * 3 + 1", &precedence),
+            Ok(27021597764222980)
+        );
+        assert_eq!(
+            evaluate("9223372036854775807 + 1", &precedence),
+            Err(Error::Overflow)
+        );
+        assert!(matches!(
+            evaluate("1 + (2", &precedence),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            evaluate("1 + x", &precedence),
+            Err(Error::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn parenthesised() {
+        let line = "1 + 2 * 3 + 4 * 5 + 6";
+        assert_eq!(
+            parenthesise(line, &Precedence::equal()),
+            Ok("(((((1 + 2) * 3) + 4) * 5) + 6)".to_string())
+        );
+        assert_eq!(
+            parenthesise(line, &Precedence::addition_first()),
+            Ok("(((1 + 2) * (3 + 4)) * (5 + 6))".to_string())
+        );
+        assert_eq!(
+            parenthesise("(2 * (3))", &Precedence::equal()),
+            Ok("(2 * 3)".to_string())
+        );
+    }
+
+    #[test]
+    fn traces() {
+        let line = "1 + 2 * 3 + 4 * 5 + 6";
+        assert_eq!(
+            trace(line, &Precedence::equal()).unwrap(),
+            vec![
+                line,
+                "3 * 3 + 4 * 5 + 6",
+                "9 + 4 * 5 + 6",
+                "13 * 5 + 6",
+                "65 + 6",
+                "71"
+            ]
+        );
+        assert_eq!(
+            trace(line, &Precedence::addition_first()).unwrap(),
+            vec![
+                line,
+                "3 * 3 + 4 * 5 + 6",
+                "3 * 7 * 5 + 6",
+                "3 * 7 * 11",
+                "21 * 11",
+                "231"
+            ]
+        );
+        let line = "1 + (2 * 3) + (4 * (5 + 6))";
+        assert_eq!(
+            trace(line, &Precedence::equal()).unwrap(),
+            vec![
+                line,
+                "1 + 6 + (4 * (5 + 6))",
+                "7 + (4 * (5 + 6))",
+                "7 + (4 * 11)",
+                "7 + 44",
+                "51"
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_operators() {
+        let precedence = Precedence::new(&[('+', 1), ('-', 1), ('*', 2), ('/', 2), ('%', 2)])
+            .with_prefix('-', 3);
+        assert_eq!(evaluate("10 - 3 - 2", &precedence), Ok(5));
+        assert_eq!(evaluate("7 / 2 * 2 + 7 % 2", &precedence), Ok(7));
+        assert_eq!(evaluate("-2 * 3 - -4", &precedence), Ok(-2));
+        assert_eq!(evaluate("-(1 + 2) * 3", &precedence), Ok(-9));
+        assert_eq!(
+            evaluate("1 / (2 - 2)", &precedence),
+            Err(Error::DivideByZero)
+        );
+        assert_eq!(evaluate("1 % 0", &precedence), Err(Error::DivideByZero));
+        assert_eq!(
+            Error::UnknownOperator('^').to_string(),
+            "Unknown operator '^'"
+        );
+        assert_eq!(
+            evaluate("-2 * 3", &Precedence::equal()),
+            Err(Error::UnknownOperator('-'))
+        );
+        assert_eq!(
+            trace("-(1 + 2) * 3", &precedence).unwrap(),
+            vec!["-(1 + 2) * 3", "-3 * 3", "-9"]
+        );
+        let high = Precedence::new(&[('*', 200), ('-', 100)]).with_prefix('-', 255);
+        assert_eq!(evaluate("-2 * 3 - -4", &high), Ok(-2));
+        assert_eq!(
+            parenthesise("-2 * 3 - 4", &high),
+            Ok("(((-2) * 3) - 4)".to_string())
+        );
+        let low = Precedence::new(&[('*', 200)]).with_prefix('-', 128);
+        assert_eq!(parenthesise("-2 * 3", &low), Ok("(-(2 * 3))".to_string()));
+    }
+
+    #[test]
+    fn associativity() {
+        let left = Precedence::new(&[('-', 1)]);
+        let right = Precedence::new(&[]).with_operator('-', 1, Associativity::Right);
+        assert_eq!(evaluate("10 - 3 - 2", &left), Ok(5));
+        assert_eq!(evaluate("10 - 3 - 2", &right), Ok(9));
+        assert_eq!(
+            parenthesise("10 - 3 - 2", &right),
+            Ok("(10 - (3 - 2))".to_string())
+        );
+        let high = Precedence::new(&[('+', 1)]).with_operator('*', 200, Associativity::Left);
+        assert_eq!(evaluate("1 + 2 * 3 + 4", &high), Ok(11));
+        assert_eq!(
+            parenthesise("1 + 2 * 3", &high),
+            Ok("(1 + (2 * 3))".to_string())
+        );
+        let highest = Precedence::new(&[('*', 254)]).with_operator('+', 255, Associativity::Right);
+        assert_eq!(evaluate("2 * 3 + 4 * 5", &highest), Ok(70));
+        let precedence = Precedence::new(&[('*', 1)]).with_prefix('-', 0);
+        assert_eq!(
+            parenthesise("-2 * 3", &precedence),
+            Ok("(-(2 * 3))".to_string())
+        );
+    }
+
     #[test]
     fn part2_1() {
         let input = "1 + 2 * 3 + 4 * 5 + 6";
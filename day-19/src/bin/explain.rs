@@ -0,0 +1,15 @@
+use answer::{explain_part1, explain_part2, Format};
+use std::{env, fs};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let format = match args.iter().any(|arg| arg == "--dot") {
+        true => Format::Dot,
+        false => Format::Text,
+    };
+    let file = fs::read_to_string("./input.txt").unwrap();
+    match args.iter().any(|arg| arg == "2") {
+        true => println!("{}", explain_part2(&file, format)),
+        false => println!("{}", explain_part1(&file, format)),
+    }
+}
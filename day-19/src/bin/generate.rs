@@ -0,0 +1,18 @@
+use answer::{generate_part1, generate_part2, GeneratorOptions};
+use std::{env, fs};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let arg = |i: usize, default: u64| args.get(i).map_or(default, |a| a.parse().unwrap());
+    let options = GeneratorOptions {
+        seed: arg(0, 0),
+        max_depth: arg(1, 20) as usize,
+        valid: arg(2, 100) as usize,
+        invalid: arg(3, 100) as usize,
+    };
+    let file = fs::read_to_string("./input.txt").unwrap();
+    match args.get(4).map(String::as_str) {
+        Some("2") => print!("{}", generate_part2(&file, options)),
+        _ => print!("{}", generate_part1(&file, options)),
+    }
+}
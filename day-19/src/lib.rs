@@ -1,150 +1,726 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::Display,
+};
+
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until},
-    character::{
-        complete::char as nomchar,
-        complete::{alpha1, digit1, newline, one_of, space1},
-    },
-    combinator::recognize,
+    bytes::complete::{is_not, tag, take_until},
+    character::complete::{char as nomchar, digit1, newline, space0, space1},
+    combinator::{all_consuming, map, map_res},
     multi::separated_list1,
     sequence::{delimited, tuple},
     IResult,
 };
-use regex::Regex;
 
 pub fn process_part1(input: &str) -> String {
-    let (rest, (mut rule_strs, messages)) = parse_input(input).unwrap();
+    let (rest, (rules_str, messages)) = parse_input(input).unwrap();
     assert!(rest.is_empty());
-    rule_strs.sort_by(|(i_a, _), (i_b, _)| i_a.cmp(i_b));
-    let mut regex_rule_strs: Vec<&str> = Vec::new();
-    rule_strs.into_iter().for_each(|(i, rule)| {
-        while regex_rule_strs.len() < i {
-            regex_rule_strs.push("");
-        }
-        regex_rule_strs.push(rule)
-    });
-    let regex_rule_0 = parse_regex(&regex_rule_strs);
-    let matches = messages
-        .into_iter()
-        .filter(|&message| regex_rule_0.is_match(message))
-        .count();
-
-    matches.to_string()
+    let grammar = Grammar::parse(rules_str).unwrap_or_else(|e| panic!("{e}"));
+    count_matches(&grammar, &messages).to_string()
 }
 
 pub fn process_part2(input: &str) -> String {
-    let new_input = input.replace("8: 42", "8: 42 | 8 42");
-    let new_input = new_input.replace("11: 42 31", "11: 42 31 | 42 11 31");
-    let input = &new_input;
-    let (rest, (mut rule_strs, messages)) = parse_input(input).unwrap();
+    let (rest, (rules_str, messages)) = parse_input(input).unwrap();
     assert!(rest.is_empty());
-    rule_strs.sort_by(|(i_a, _), (i_b, _)| i_a.cmp(i_b));
-    let mut rule_book: Vec<&str> = Vec::new();
-    rule_strs.into_iter().for_each(|(i, rule)| {
-        while rule_book.len() < i {
-            rule_book.push("");
-        }
-        rule_book.push(rule)
-    });
-    let (rule31, rule42) = parse_regex2(&rule_book);
-    let matches = messages
-        .into_iter()
-        .filter(|&message| {
-            let mut pos = 0;
-            let mut count42 = 0;
-            let mut count31 = 0;
-            while let Some(mtch) = rule42.find_at(message, pos) {
-                if mtch.start() != pos {
-                    break;
+    let mut grammar = Grammar::parse(rules_str).unwrap_or_else(|e| panic!("{e}"));
+    grammar.replace_rule("8: 42 | 42 8").unwrap();
+    grammar.replace_rule("11: 42 31 | 42 11 31").unwrap();
+    count_matches(&grammar, &messages).to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Dot,
+}
+
+pub fn explain_part1(input: &str, format: Format) -> String {
+    let (_, (rules_str, messages)) = parse_input(input).unwrap();
+    let grammar = Grammar::parse(rules_str).unwrap_or_else(|e| panic!("{e}"));
+    explain_messages(&grammar, &messages, format)
+}
+
+pub fn explain_part2(input: &str, format: Format) -> String {
+    let (_, (rules_str, messages)) = parse_input(input).unwrap();
+    let mut grammar = Grammar::parse(rules_str).unwrap_or_else(|e| panic!("{e}"));
+    grammar.replace_rule("8: 42 | 42 8").unwrap();
+    grammar.replace_rule("11: 42 31 | 42 11 31").unwrap();
+    explain_messages(&grammar, &messages, format)
+}
+
+fn explain_messages(grammar: &Grammar, messages: &[&str], format: Format) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            let explanation = grammar.explain(message);
+            match format {
+                Format::Text => explanation.to_text(message),
+                Format::Dot => explanation.to_dot(message),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneratorOptions {
+    pub seed: u64,
+    pub max_depth: usize,
+    pub valid: usize,
+    pub invalid: usize,
+}
+
+pub fn generate_part1(input: &str, options: GeneratorOptions) -> String {
+    let (_, (rules_str, _)) = parse_input(input).unwrap();
+    let grammar = Grammar::parse(rules_str).unwrap_or_else(|e| panic!("{e}"));
+    let messages = Generator::new(&grammar, options).messages();
+    format!("{grammar}\n\n{}", messages.join("\n"))
+}
+
+pub fn generate_part2(input: &str, options: GeneratorOptions) -> String {
+    let (_, (rules_str, _)) = parse_input(input).unwrap();
+    let grammar = Grammar::parse(rules_str).unwrap_or_else(|e| panic!("{e}"));
+    let mut looping = grammar.clone();
+    looping.replace_rule("8: 42 | 42 8").unwrap();
+    looping.replace_rule("11: 42 31 | 42 11 31").unwrap();
+    let messages = Generator::new(&looping, options).messages();
+    format!("{grammar}\n\n{}", messages.join("\n"))
+}
+
+fn count_matches(grammar: &Grammar, messages: &[&str]) -> usize {
+    messages
+        .iter()
+        .filter(|message| grammar.matches(message))
+        .count()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Symbol {
+    Rule(usize),
+    Terminal(String),
+}
+
+type Alternative = Vec<Symbol>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GrammarError {
+    Parse { line: usize, text: String },
+    DuplicateRule(usize),
+    UndefinedRule { rule: usize, referenced_by: usize },
+    UnreachableRules(Vec<usize>),
+}
+
+impl Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse { line, text } => write!(f, "cannot parse rule on line {line}: '{text}'"),
+            Self::DuplicateRule(rule) => write!(f, "rule {rule} is defined more than once"),
+            Self::UndefinedRule {
+                rule,
+                referenced_by,
+            } => write!(f, "rule {referenced_by} refers to undefined rule {rule}"),
+            Self::UnreachableRules(rules) => {
+                write!(f, "rules not reachable from rule 0: {rules:?}")
+            }
+        }
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rule(rule) => write!(f, "{rule}"),
+            Self::Terminal(terminal) => write!(f, "\"{terminal}\""),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Grammar {
+    rules: HashMap<usize, Vec<Alternative>>,
+}
+
+impl Grammar {
+    fn parse(rules_str: &str) -> Result<Self, GrammarError> {
+        let mut grammar = Self::default();
+        for (n, line) in rules_str.lines().enumerate() {
+            let (i, alternatives) = parse_rule_line(line).map_err(|_| GrammarError::Parse {
+                line: n + 1,
+                text: line.to_string(),
+            })?;
+            if grammar.rules.insert(i, alternatives).is_some() {
+                return Err(GrammarError::DuplicateRule(i));
+            }
+        }
+        grammar.check()?;
+        Ok(grammar)
+    }
+
+    fn replace_rule(&mut self, line: &str) -> Result<(), GrammarError> {
+        let (i, alternatives) = parse_rule_line(line).map_err(|_| GrammarError::Parse {
+            line: 1,
+            text: line.to_string(),
+        })?;
+        let previous = self.rules.insert(i, alternatives);
+        if let Err(e) = self.check() {
+            match previous {
+                Some(alternatives) => self.rules.insert(i, alternatives),
+                None => self.rules.remove(&i),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), GrammarError> {
+        let mut ids: Vec<_> = self.rules.keys().copied().collect();
+        ids.sort();
+        for &id in &ids {
+            for symbol in self.rules[&id].iter().flatten() {
+                if let &Symbol::Rule(rule) = symbol {
+                    if !self.rules.contains_key(&rule) {
+                        return Err(GrammarError::UndefinedRule {
+                            rule,
+                            referenced_by: id,
+                        });
+                    }
                 }
-                count42 += 1;
-                pos = mtch.end();
             }
-            while let Some(mtch) = rule31.find_at(message, pos) {
-                if mtch.start() != pos {
-                    break;
+        }
+        let mut reachable = HashSet::from([0]);
+        let mut stack = vec![0];
+        while let Some(id) = stack.pop() {
+            for symbol in self.rules.get(&id).into_iter().flatten().flatten() {
+                if let &Symbol::Rule(rule) = symbol {
+                    if reachable.insert(rule) {
+                        stack.push(rule);
+                    }
                 }
-                count31 += 1;
-                pos = mtch.end();
             }
+        }
+        let unreachable: Vec<_> = ids
+            .into_iter()
+            .filter(|id| !reachable.contains(id))
+            .collect();
+        if !unreachable.is_empty() {
+            return Err(GrammarError::UnreachableRules(unreachable));
+        }
+        Ok(())
+    }
 
-            (pos == message.len()) && (count31 > 0) && (count31 < count42)
-        })
-        .count();
+    fn matches(&self, message: &str) -> bool {
+        Matcher::new(self, message)
+            .ends(0, 0)
+            .contains(&message.len())
+    }
 
-    matches.to_string()
+    fn explain(&self, message: &str) -> Explanation {
+        let mut matcher = Matcher::new(self, message);
+        let ends = matcher.ends(0, 0);
+        if ends.contains(&message.len()) {
+            let derivation = matcher
+                .derive(0, 0, message.len(), &mut HashSet::new())
+                .unwrap();
+            return Explanation::Match(derivation);
+        }
+        match ends.last() {
+            Some(&end) if end >= matcher.furthest => Explanation::NoMatch {
+                matched: end,
+                rules: vec![0],
+            },
+            _ => Explanation::NoMatch {
+                matched: matcher.furthest,
+                rules: matcher.failed.into_iter().collect(),
+            },
+        }
+    }
 }
 
-fn parse_input(input: &str) -> IResult<&str, (Vec<(usize, &str)>, Vec<&str>)> {
-    let (input, rules_str) = take_until("\n\n")(input)?;
-    let (input, _) = tag("\n\n")(input)?;
-    let (rest, rules) = separated_list1(newline, parse_rule_str)(rules_str)?;
-    assert!(rest.is_empty());
-    let (input, messages) = separated_list1(newline, alpha1)(input)?;
-    Ok((input, (rules, messages)))
-}
-
-fn parse_rule_str(input: &str) -> IResult<&str, (usize, &str)> {
-    let (input, (i, _, rule)) = tuple((
-        digit1,
-        tag(": "),
-        alt((
-            recognize(tuple((nomchar('"'), one_of("ab"), nomchar('"')))), // character
-            recognize(tuple((digit1, space1, digit1, space1, digit1))),   // three rules
-            recognize(delimited(
-                tuple((digit1, space1, digit1)),
-                tag(" | "),
-                tuple((digit1, space1, digit1, space1, digit1)),
-            )), // two rules | three rules
-            recognize(delimited(
-                tuple((digit1, space1, digit1)),
-                tag(" | "),
-                tuple((digit1, space1, digit1)),
-            )), // two rules | two rules
-            recognize(tuple((digit1, space1, digit1))),                   // two rules
-            recognize(delimited(
-                digit1,
-                tag(" | "),
-                tuple((digit1, space1, digit1)),
-            )), // one rule | two rules
-            recognize(delimited(digit1, tag(" | "), digit1)),             // one rule | one rule
-            recognize(digit1),                                            // single rule
-        )),
-    ))(input)?;
-    let i = i.parse::<usize>().unwrap();
-    Ok((input, (i, rule)))
-}
-
-fn parse_recursive(rule_book: &Vec<&str>, r: usize) -> String {
-    let rule_s = rule_book[r];
-    if let IResult::<_, _>::Ok((_, c)) =
-        delimited(nomchar('"'), recognize(one_of("ab")), nomchar('"'))(rule_s)
-    {
-        c.to_string()
-    } else {
-        let parts = rule_s.split(' ').collect::<Vec<_>>();
-        let inner_part = parts
+impl Display for Grammar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ids: Vec<_> = self.rules.keys().copied().collect();
+        ids.sort();
+        let lines: Vec<_> = ids
             .into_iter()
-            .map(|p| match p {
-                "|" => p.to_string(),
-                s => parse_recursive(rule_book, s.parse().unwrap()),
+            .map(|id| {
+                let alternatives: Vec<_> = self.rules[&id]
+                    .iter()
+                    .map(|alternative| {
+                        alternative
+                            .iter()
+                            .map(|symbol| symbol.to_string())
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .collect();
+                format!("{id}: {}", alternatives.join(" | "))
             })
-            .collect::<String>();
-        format!("({})", inner_part)
+            .collect();
+        write!(f, "{}", lines.join("\n"))
     }
 }
 
-fn parse_regex(rule_book: &Vec<&str>) -> Regex {
-    let regex_str = format!("^{}$", parse_recursive(rule_book, 0));
-    Regex::new(&regex_str).unwrap()
+// Memoised matcher: for every (rule, position) it records the set of positions
+// a match of that rule starting there can end at. Left-recursive rules see the
+// partial result for themselves, so the table is recomputed until it is stable.
+struct Matcher<'a> {
+    grammar: &'a Grammar,
+    message: &'a [u8],
+    table: HashMap<(usize, usize), BTreeSet<usize>>,
+    done: HashSet<(usize, usize)>,
+    active: HashSet<(usize, usize)>,
+    stack: Vec<usize>,
+    cyclic: bool,
+    changed: bool,
+    furthest: usize,
+    failed: BTreeSet<usize>,
 }
 
-fn parse_regex2(rule_book: &Vec<&str>) -> (Regex, Regex) {
-    let rule_31_str = parse_recursive(rule_book, 31);
-    let rule_31 = Regex::new(&rule_31_str).unwrap();
-    let rule_42_str = parse_recursive(rule_book, 42);
-    let rule_42 = Regex::new(&rule_42_str).unwrap();
-    (rule_31, rule_42)
+impl<'a> Matcher<'a> {
+    fn new(grammar: &'a Grammar, message: &'a str) -> Self {
+        Self {
+            grammar,
+            message: message.as_bytes(),
+            table: HashMap::new(),
+            done: HashSet::new(),
+            active: HashSet::new(),
+            stack: Vec::new(),
+            cyclic: false,
+            changed: false,
+            furthest: 0,
+            failed: BTreeSet::new(),
+        }
+    }
+
+    fn ends(&mut self, rule: usize, pos: usize) -> BTreeSet<usize> {
+        loop {
+            self.done.clear();
+            self.cyclic = false;
+            self.changed = false;
+            let ends = self.rule_ends(rule, pos);
+            if !self.cyclic || !self.changed {
+                return ends;
+            }
+        }
+    }
+
+    fn rule_ends(&mut self, rule: usize, pos: usize) -> BTreeSet<usize> {
+        let key = (rule, pos);
+        if self.active.contains(&key) {
+            self.cyclic = true;
+        }
+        if self.done.contains(&key) || self.active.contains(&key) {
+            return self.table.get(&key).cloned().unwrap_or_default();
+        }
+        let Some(alternatives) = self.grammar.rules.get(&rule) else {
+            return BTreeSet::new();
+        };
+        self.active.insert(key);
+        self.stack.push(rule);
+        let mut ends = BTreeSet::new();
+        for alternative in alternatives {
+            let mut positions = BTreeSet::from([pos]);
+            for symbol in alternative {
+                positions = positions
+                    .into_iter()
+                    .flat_map(|p| self.symbol_ends(symbol, p))
+                    .collect();
+            }
+            ends.extend(positions);
+        }
+        self.stack.pop();
+        self.active.remove(&key);
+        self.done.insert(key);
+        let entry = self.table.entry(key).or_default();
+        if ends.iter().any(|end| !entry.contains(end)) {
+            entry.extend(ends.iter().copied());
+            self.changed = true;
+        }
+        entry.clone()
+    }
+
+    fn symbol_ends(&mut self, symbol: &Symbol, pos: usize) -> BTreeSet<usize> {
+        match symbol {
+            &Symbol::Rule(rule) => self.rule_ends(rule, pos),
+            Symbol::Terminal(terminal) => {
+                if self.message[pos..].starts_with(terminal.as_bytes()) {
+                    BTreeSet::from([pos + terminal.len()])
+                } else {
+                    if pos > self.furthest {
+                        self.furthest = pos;
+                        self.failed.clear();
+                    }
+                    if pos == self.furthest {
+                        self.failed.extend(self.stack.last());
+                    }
+                    BTreeSet::new()
+                }
+            }
+        }
+    }
+
+    // Only valid once `ends` has been run for rule 0, so every span that can
+    // appear in the derivation is already in the table.
+    fn derive(
+        &mut self,
+        rule: usize,
+        start: usize,
+        end: usize,
+        visiting: &mut HashSet<(usize, usize, usize)>,
+    ) -> Option<Derivation> {
+        if !visiting.insert((rule, start, end)) {
+            return None;
+        }
+        let grammar = self.grammar;
+        let mut derivation = None;
+        for alternative in grammar.rules.get(&rule).into_iter().flatten() {
+            if let Some(children) = self.derive_sequence(alternative, start, end, visiting) {
+                derivation = Some(Derivation::Rule {
+                    rule,
+                    start,
+                    end,
+                    children,
+                });
+                break;
+            }
+        }
+        visiting.remove(&(rule, start, end));
+        derivation
+    }
+
+    fn derive_sequence(
+        &mut self,
+        symbols: &[Symbol],
+        start: usize,
+        end: usize,
+        visiting: &mut HashSet<(usize, usize, usize)>,
+    ) -> Option<Vec<Derivation>> {
+        let Some((first, rest)) = symbols.split_first() else {
+            return (start == end).then(Vec::new);
+        };
+        match first {
+            Symbol::Terminal(terminal) => {
+                if !self.message[start..].starts_with(terminal.as_bytes()) {
+                    return None;
+                }
+                let mid = start + terminal.len();
+                let mut children = self.derive_sequence(rest, mid, end, visiting)?;
+                children.insert(0, Derivation::Terminal { start, end: mid });
+                Some(children)
+            }
+            &Symbol::Rule(rule) => {
+                for mid in self.rule_ends(rule, start).range(..=end) {
+                    let Some(mut children) = self.derive_sequence(rest, *mid, end, visiting) else {
+                        continue;
+                    };
+                    if let Some(child) = self.derive(rule, start, *mid, visiting) {
+                        children.insert(0, child);
+                        return Some(children);
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Derivation {
+    Rule {
+        rule: usize,
+        start: usize,
+        end: usize,
+        children: Vec<Derivation>,
+    },
+    Terminal {
+        start: usize,
+        end: usize,
+    },
+}
+
+impl Derivation {
+    fn write_text(&self, message: &str, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        match self {
+            Self::Rule {
+                rule,
+                start,
+                end,
+                children,
+            } => {
+                out.push_str(&format!(
+                    "{indent}{rule} [{start}..{end}] {}\n",
+                    &message[*start..*end]
+                ));
+                children
+                    .iter()
+                    .for_each(|child| child.write_text(message, depth + 1, out));
+            }
+            Self::Terminal { start, end } => {
+                out.push_str(&format!("{indent}{:?}\n", &message[*start..*end]));
+            }
+        }
+    }
+
+    fn write_dot(&self, message: &str, next_id: &mut usize, out: &mut String) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        match self {
+            Self::Rule {
+                rule,
+                start,
+                end,
+                children,
+            } => {
+                let label = format!("{rule}: {}", &message[*start..*end]);
+                out.push_str(&format!("    n{id} [label={label:?}];\n"));
+                for child in children {
+                    let child_id = child.write_dot(message, next_id, out);
+                    out.push_str(&format!("    n{id} -> n{child_id};\n"));
+                }
+            }
+            Self::Terminal { start, end } => {
+                let label = format!("{:?}", &message[*start..*end]);
+                out.push_str(&format!("    n{id} [label={label:?}, shape=box];\n"));
+            }
+        }
+        id
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Explanation {
+    Match(Derivation),
+    NoMatch { matched: usize, rules: Vec<usize> },
+}
+
+impl Explanation {
+    fn to_text(&self, message: &str) -> String {
+        match self {
+            Self::Match(derivation) => {
+                let mut out = format!("{message}: match\n");
+                derivation.write_text(message, 1, &mut out);
+                out
+            }
+            Self::NoMatch { matched, rules } => format!(
+                "{message}: no match\n  matched prefix: {:?}\n  failed in rules: {rules:?}\n",
+                &message[..*matched]
+            ),
+        }
+    }
+
+    fn to_dot(&self, message: &str) -> String {
+        let mut out = String::from("digraph derivation {\n");
+        out.push_str(&format!("    label={message:?};\n"));
+        match self {
+            Self::Match(derivation) => {
+                derivation.write_dot(message, &mut 0, &mut out);
+            }
+            Self::NoMatch { matched, rules } => {
+                let label = format!("matched: {}", &message[..*matched]);
+                out.push_str(&format!("    prefix [label={label:?}, shape=box];\n"));
+                for rule in rules {
+                    out.push_str(&format!(
+                        "    r{rule} [label=\"{rule}\", color=red];\n    prefix -> r{rule} [label=\"failed at {matched}\"];\n"
+                    ));
+                }
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+// splitmix64, so generated messages only depend on the seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+struct Generator<'a> {
+    grammar: &'a Grammar,
+    options: GeneratorOptions,
+    rng: Rng,
+    heights: HashMap<usize, usize>,
+    alphabet: Vec<char>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(grammar: &'a Grammar, options: GeneratorOptions) -> Self {
+        let mut alphabet: Vec<char> = grammar
+            .rules
+            .values()
+            .flatten()
+            .flatten()
+            .filter_map(|symbol| match symbol {
+                Symbol::Terminal(terminal) => Some(terminal.chars()),
+                Symbol::Rule(_) => None,
+            })
+            .flatten()
+            .collect();
+        alphabet.sort();
+        alphabet.dedup();
+        let mut generator = Self {
+            grammar,
+            options,
+            rng: Rng(options.seed),
+            heights: HashMap::new(),
+            alphabet,
+        };
+        generator.heights = generator.heights();
+        generator
+    }
+
+    // Height of the shallowest derivation of every rule, used to steer
+    // towards terminals once the depth limit is reached.
+    fn heights(&self) -> HashMap<usize, usize> {
+        let mut heights = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (&id, alternatives) in &self.grammar.rules {
+                let best = alternatives
+                    .iter()
+                    .filter_map(|alternative| alternative_height(alternative, &heights))
+                    .min();
+                if let Some(height) = best {
+                    if heights.get(&id).is_none_or(|&h| height < h) {
+                        heights.insert(id, height);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        heights
+    }
+
+    fn messages(&mut self) -> Vec<String> {
+        let mut messages: Vec<_> = (0..self.options.valid).map(|_| self.valid()).collect();
+        let mut attempts = 0;
+        let mut invalid = 0;
+        while invalid < self.options.invalid && attempts < 100 * self.options.invalid {
+            attempts += 1;
+            let message = self.valid();
+            let mutated = self.mutate(&message);
+            if !mutated.is_empty() && !self.grammar.matches(&mutated) {
+                messages.push(mutated);
+                invalid += 1;
+            }
+        }
+        for i in (1..messages.len()).rev() {
+            let j = self.rng.below(i + 1);
+            messages.swap(i, j);
+        }
+        messages
+    }
+
+    fn valid(&mut self) -> String {
+        let mut message = String::new();
+        self.generate(0, 0, &mut message);
+        message
+    }
+
+    fn generate(&mut self, rule: usize, depth: usize, out: &mut String) {
+        let grammar = self.grammar;
+        let finite: Vec<_> = grammar.rules[&rule]
+            .iter()
+            .filter_map(|alternative| {
+                alternative_height(alternative, &self.heights).map(|h| (h, alternative))
+            })
+            .collect();
+        assert!(!finite.is_empty(), "rule {rule} never reaches a terminal");
+        let alternative = if depth >= self.options.max_depth {
+            finite.iter().min_by_key(|(h, _)| h).unwrap().1
+        } else {
+            finite[self.rng.below(finite.len())].1
+        };
+        for symbol in alternative {
+            match symbol {
+                &Symbol::Rule(rule) => self.generate(rule, depth + 1, out),
+                Symbol::Terminal(terminal) => out.push_str(terminal),
+            }
+        }
+    }
+
+    // Never returns an empty message: it would be a blank line in the output.
+    fn mutate(&mut self, message: &str) -> String {
+        let mut chars: Vec<char> = message.chars().collect();
+        if chars.is_empty() {
+            return self.alphabet[self.rng.below(self.alphabet.len())].to_string();
+        }
+        let i = self.rng.below(chars.len());
+        match self.rng.below(3) {
+            0 => {
+                let c = self.alphabet[self.rng.below(self.alphabet.len())];
+                chars[i] = c;
+            }
+            1 if chars.len() > 1 => {
+                chars.remove(i);
+            }
+            _ => {
+                let c = self.alphabet[self.rng.below(self.alphabet.len())];
+                chars.insert(i, c);
+            }
+        }
+        chars.into_iter().collect()
+    }
+}
+
+fn alternative_height(alternative: &[Symbol], heights: &HashMap<usize, usize>) -> Option<usize> {
+    alternative
+        .iter()
+        .map(|symbol| match symbol {
+            Symbol::Rule(rule) => heights.get(rule).copied(),
+            Symbol::Terminal(_) => Some(0),
+        })
+        .try_fold(0, |max, height| Some(max.max(height?)))
+        .map(|height| height + 1)
+}
+
+fn parse_input(input: &str) -> IResult<&str, (&str, Vec<&str>)> {
+    let (input, rules_str) = take_until("\n\n")(input)?;
+    let (input, _) = tag("\n\n")(input)?;
+    let (input, messages) = separated_list1(newline, is_not("\r\n"))(input)?;
+    Ok((input, (rules_str, messages)))
+}
+
+fn parse_rule_line(
+    line: &str,
+) -> Result<(usize, Vec<Alternative>), nom::Err<nom::error::Error<&str>>> {
+    let (_, rule) = all_consuming(parse_rule)(line.trim_end())?;
+    Ok(rule)
+}
+
+fn parse_rule(input: &str) -> IResult<&str, (usize, Vec<Alternative>)> {
+    let (input, i) = map_res(digit1, str::parse)(input)?;
+    let (input, _) = tuple((nomchar(':'), space0))(input)?;
+    let (input, alternatives) = separated_list1(
+        tuple((space0, nomchar('|'), space0)),
+        separated_list1(space1, parse_symbol),
+    )(input)?;
+    Ok((input, (i, alternatives)))
+}
+
+fn parse_symbol(input: &str) -> IResult<&str, Symbol> {
+    alt((
+        map(map_res(digit1, str::parse), Symbol::Rule),
+        map(
+            delimited(nomchar('"'), is_not("\""), nomchar('"')),
+            |terminal: &str| Symbol::Terminal(terminal.to_string()),
+        ),
+    ))(input)
 }
 
 #[cfg(test)]
@@ -223,4 +799,176 @@ aabbbbbaabbbaaaaaabbbbbababaaaaabbaaabba";
         let result2 = process_part2(input);
         assert_eq!(result2, "12");
     }
+
+    #[test]
+    fn left_recursion() {
+        let rules = "0: 1 2
+1: 1 3 | 3
+2: \"b\"
+3: \"a\"";
+        let mut grammar = Grammar::parse(rules).unwrap();
+        assert!(grammar.matches("ab"));
+        assert!(grammar.matches("aaab"));
+        assert!(!grammar.matches("b"));
+        assert!(!grammar.matches("aaba"));
+        grammar.replace_rule("1: 1 1 | 3").unwrap();
+        assert!(grammar.matches("aaaab"));
+    }
+
+    #[test]
+    fn flexible_rules() {
+        let rules = "0: 1 \"-\" 2 | 2 2 2 2 | \"xyz\"
+1: \"hello\" | \"hi\"
+2: \"ü\" 2 | \"w\"";
+        let grammar = Grammar::parse(rules).unwrap();
+        assert!(grammar.matches("hello-w"));
+        assert!(grammar.matches("hi-üüw"));
+        assert!(grammar.matches("wwüww"));
+        assert!(grammar.matches("xyz"));
+        assert!(!grammar.matches("hi-"));
+        assert!(!grammar.matches("www"));
+    }
+
+    #[test]
+    fn grammar_errors() {
+        assert_eq!(
+            Grammar::parse("0: 1 2\n1: \"a\"").unwrap_err(),
+            GrammarError::UndefinedRule {
+                rule: 2,
+                referenced_by: 0
+            }
+        );
+        assert_eq!(
+            Grammar::parse("0: 1\n1: \"a\"\n2: 1\n3: \"b\"").unwrap_err(),
+            GrammarError::UnreachableRules(vec![2, 3])
+        );
+        assert_eq!(
+            Grammar::parse("0: 1\n1: \"a\" |").unwrap_err(),
+            GrammarError::Parse {
+                line: 2,
+                text: "1: \"a\" |".to_string()
+            }
+        );
+        assert_eq!(
+            Grammar::parse("0: \"a\"\n0: \"b\"").unwrap_err(),
+            GrammarError::DuplicateRule(0)
+        );
+        let mut grammar = Grammar::parse("0: 1\n1: \"a\"").unwrap();
+        assert!(grammar.replace_rule("0: 1 5").is_err());
+        assert!(grammar.matches("a"));
+    }
+
+    #[test]
+    fn explanations() {
+        let rules = "0: 4 1 5
+1: 2 3 | 3 2
+2: 4 4 | 5 5
+3: 4 5 | 5 4
+4: \"a\"
+5: \"b\"";
+        let grammar = Grammar::parse(rules).unwrap();
+        let explanation = grammar.explain("ababbb");
+        assert_eq!(
+            explanation.to_text("ababbb"),
+            "ababbb: match
+  0 [0..6] ababbb
+    4 [0..1] a
+      \"a\"
+    1 [1..5] babb
+      3 [1..3] ba
+        5 [1..2] b
+          \"b\"
+        4 [2..3] a
+          \"a\"
+      2 [3..5] bb
+        5 [3..4] b
+          \"b\"
+        5 [4..5] b
+          \"b\"
+    5 [5..6] b
+      \"b\"
+"
+        );
+        assert!(explanation.to_dot("ababbb").contains(
+            "n1 [label=\"4: a\"];\n    n2 [label=\"\\\"a\\\"\", shape=box];\n    n1 -> n2;"
+        ));
+        assert_eq!(
+            grammar.explain("aaabbb"),
+            Explanation::NoMatch {
+                matched: 4,
+                rules: vec![4]
+            }
+        );
+        assert_eq!(
+            grammar.explain("aaaabbb"),
+            Explanation::NoMatch {
+                matched: 6,
+                rules: vec![0]
+            }
+        );
+        assert_eq!(
+            grammar.explain("bababa").to_text("bababa"),
+            "bababa: no match\n  matched prefix: \"\"\n  failed in rules: [4]\n"
+        );
+    }
+
+    #[test]
+    fn generated_round_trip() {
+        let input = "0: 4 1 5
+1: 2 3 | 3 2
+2: 4 4 | 5 5
+3: 4 5 | 5 4
+4: \"a\"
+5: \"b\"
+
+ababbb";
+        let options = GeneratorOptions {
+            seed: 19,
+            max_depth: 10,
+            valid: 20,
+            invalid: 15,
+        };
+        let generated = generate_part1(input, options);
+        assert!(generated.starts_with("0: 4 1 5\n1: 2 3 | 3 2\n"));
+        assert_eq!(generated.lines().count(), 6 + 1 + 35);
+        assert_eq!(process_part1(&generated), "20");
+        assert_eq!(generated, generate_part1(input, options));
+    }
+
+    #[test]
+    fn generated_round_trip_loops() {
+        let input = "42: 1 | 2 42
+31: 2 1
+8: 42
+11: 42 31
+0: 8 11
+1: \"a\"
+2: \"b\"
+
+ab";
+        let options = GeneratorOptions {
+            seed: 7,
+            max_depth: 6,
+            valid: 25,
+            invalid: 10,
+        };
+        let generated = generate_part2(input, options);
+        assert_eq!(process_part2(&generated), "25");
+    }
+
+    #[test]
+    fn generated_single_characters() {
+        let input = "0: 1\n1: \"a\"\n\na";
+        for seed in 0..20 {
+            let options = GeneratorOptions {
+                seed,
+                max_depth: 3,
+                valid: 2,
+                invalid: 3,
+            };
+            let generated = generate_part1(input, options);
+            assert!(!generated.contains("\n\n\n"));
+            assert_eq!(process_part1(&generated), "2");
+        }
+    }
 }
@@ -0,0 +1,28 @@
+use answer::{process_part1, process_part1_backtracking, synthetic_input};
+use std::{env, fs, time::Instant};
+
+fn main() {
+    let file = fs::read_to_string("./input.txt").unwrap();
+    let mut inputs = vec![("input.txt".to_string(), file)];
+    let sizes: Vec<usize> = match env::args().nth(1) {
+        Some(arg) => arg.split(',').map(|n| n.parse().unwrap()).collect(),
+        None => vec![12, 24, 36],
+    };
+    for dimension in sizes {
+        let name = format!("synthetic {dimension}x{dimension}");
+        inputs.push((
+            name,
+            synthetic_input(dimension as u64, dimension, dimension, 24),
+        ));
+    }
+    for (name, input) in inputs {
+        let start = Instant::now();
+        let greedy = process_part1(&input);
+        let greedy_time = start.elapsed();
+        let start = Instant::now();
+        let backtracking = process_part1_backtracking(&input);
+        let backtracking_time = start.elapsed();
+        assert_eq!(greedy, backtracking);
+        println!("{name}: edge index {greedy_time:?}, backtracking {backtracking_time:?}");
+    }
+}
@@ -0,0 +1,13 @@
+use answer::{assemble_input, highlighted, render_image, render_png, tile_grid, SEA_MONSTER};
+use std::{env, fs};
+
+fn main() {
+    let file = fs::read_to_string("./input.txt").unwrap();
+    let path = env::args().nth(1).unwrap_or("image.png".to_string());
+    let assembled = assemble_input(&file);
+    let pixels = highlighted(&assembled, SEA_MONSTER);
+    println!("{}\n", tile_grid(&assembled));
+    println!("{}", render_image(&pixels));
+    let png = render_png(&pixels, 4).unwrap_or_else(|e| panic!("{e}"));
+    fs::write(&path, png).unwrap();
+}
@@ -1,7 +1,11 @@
-use answer::process_part2;
-use std::fs;
+use answer::{process_part2_with, SEA_MONSTER};
+use std::{env, fs};
 
 fn main() {
     let file = fs::read_to_string("./input.txt").unwrap();
-    println!("{}", process_part2(&file));
+    let pattern = match env::args().nth(1) {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => SEA_MONSTER.to_string(),
+    };
+    println!("{}", process_part2_with(&file, &pattern));
 }
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+};
 
 use nom::{
     bytes::complete::tag,
@@ -9,126 +12,469 @@ use nom::{
 };
 
 pub fn process_part1(input: &str) -> String {
-    let (_, tiles_raw) = parse_input(input).unwrap();
-    let mut tiles: HashMap<usize, Tile> = tiles_raw
-        .into_iter()
-        .map(|(id, v)| (id, Tile::new(id, v)))
-        .collect();
-    let dimension = (tiles.len() as f64).sqrt() as usize;
-
-    let mut solution = Vec::new();
-    if solve(&mut solution, &mut tiles, dimension) {
-        let mut n = 1;
-        let first = solution.first().unwrap();
-        n *= first.first().unwrap().id;
-        n *= first.last().unwrap().id;
-        let last = solution.last().unwrap();
-        n *= last.first().unwrap().id;
-        n *= last.last().unwrap().id;
-        format!("{}", n)
-    } else {
-        "".to_string()
-    }
+    let mut tiles = load_tiles(input).unwrap_or_else(|e| panic!("{e}"));
+    let solution = assemble(&mut tiles).unwrap_or_else(|e| panic!("{e}"));
+    corner_product(&solution).to_string()
 }
 
+pub fn process_part1_backtracking(input: &str) -> String {
+    let mut tiles = load_tiles(input).unwrap_or_else(|e| panic!("{e}"));
+    let solution = assemble_backtracking(&mut tiles).unwrap_or_else(|e| panic!("{e}"));
+    corner_product(&solution).to_string()
+}
+
+fn corner_product(solution: &Image) -> usize {
+    let first = solution.first().unwrap();
+    let last = solution.last().unwrap();
+    first.first().unwrap().id
+        * first.last().unwrap().id
+        * last.first().unwrap().id
+        * last.last().unwrap().id
+}
+
+pub const SEA_MONSTER: &str = "                  # 
+#    ##    ##    ###
+ #  #  #  #  #  #   ";
+
 pub fn process_part2(input: &str) -> String {
-    // parse tiles
-    let (_, tiles_raw) = parse_input(input).unwrap();
-    let mut tiles: HashMap<usize, Tile> = tiles_raw
-        .into_iter()
-        .map(|(id, v)| (id, Tile::new(id, v)))
+    process_part2_with(input, SEA_MONSTER)
+}
+
+pub fn process_part2_with(input: &str, pattern: &str) -> String {
+    let image = stitch(&assemble_input(input).0);
+    let template = Template::parse(pattern);
+    let (transform, matches) = search_image(&image, &template);
+    if matches.is_empty() {
+        return "NOT FOUND".to_string();
+    }
+
+    // count pixels that are on and not part of any match
+    let image = transform.apply(&image);
+    let marked = mark_matches(&image, &template, &matches);
+    image
+        .iter()
+        .flatten()
+        .zip(marked.iter().flatten())
+        .filter(|&(&pixel, &marked)| pixel == Pixel::On && !marked)
+        .count()
+        .to_string()
+}
+
+/// Finds `pattern` in the orientation of the image with the most matches.
+/// Each match is the (row, column) of its top left corner in
+/// `transform.apply(&stitched_image(assembled))`, where `transform` is the
+/// returned orientation.
+pub fn find_pattern(assembled: &Assembled, pattern: &str) -> (D4, Vec<(usize, usize)>) {
+    search_image(&stitch(&assembled.0), &Template::parse(pattern))
+}
+
+fn search_image(image: &[Vec<Pixel>], template: &Template) -> (D4, Vec<(usize, usize)>) {
+    D4::ALL
+        .iter()
+        .map(|&transform| (transform, template.find(&transform.apply(image))))
+        .max_by_key(|(_, matches)| matches.len())
+        .unwrap()
+}
+
+/// The tiles of the input fitted together, so it can be rendered several ways
+/// without assembling it again.
+pub struct Assembled(Image);
+
+pub fn assemble_input(input: &str) -> Assembled {
+    let mut tiles = load_tiles(input).unwrap_or_else(|e| panic!("{e}"));
+    Assembled(assemble(&mut tiles).unwrap_or_else(|e| panic!("{e}")))
+}
+
+pub fn tile_grid(assembled: &Assembled) -> String {
+    assembled
+        .0
+        .iter()
+        .map(|tile_row| {
+            tile_row
+                .iter()
+                .map(|tile| tile.id.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn render_image(highlighted: &[Vec<char>]) -> String {
+    highlighted
+        .iter()
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenderError {
+    ZeroScale,
+    EmptyImage,
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::ZeroScale => write!(f, "Scale has to be at least 1"),
+            RenderError::EmptyImage => write!(f, "There are no pixels to draw"),
+        }
+    }
+}
+
+pub fn render_png(highlighted: &[Vec<char>], scale: usize) -> Result<Vec<u8>, RenderError> {
+    if scale == 0 {
+        return Err(RenderError::ZeroScale);
+    }
+    let height = highlighted.len() * scale;
+    let width = highlighted.first().map_or(0, |row| row.len()) * scale;
+    if height == 0 || width == 0 {
+        return Err(RenderError::EmptyImage);
+    }
+    let data: Vec<u8> = highlighted
+        .iter()
+        .flat_map(|row| {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|&c| {
+                    let colour: [u8; 3] = match c {
+                        'O' => [0xe0, 0x40, 0x30],
+                        '#' => [0x40, 0x90, 0xd0],
+                        _ => [0x0a, 0x1a, 0x40],
+                    };
+                    colour.repeat(scale)
+                })
+                .collect();
+            line.repeat(scale)
+        })
         .collect();
-    let dimension = (tiles.len() as f64).sqrt() as usize;
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&data).unwrap();
+    writer.finish().unwrap();
+    Ok(png)
+}
 
-    // find solution
-    let mut solution = Vec::new();
-    solve(&mut solution, &mut tiles, dimension);
+/// The interior pixels of every tile as placed, with borders removed, before
+/// any search turns the image around.
+pub fn stitched_image(assembled: &Assembled) -> Vec<Vec<char>> {
+    stitch(&assembled.0)
+        .iter()
+        .map(|row| row.iter().map(Pixel::char).collect())
+        .collect()
+}
 
-    // extract image
-    let mut image: Vec<Vec<Pixel>> = vec![Vec::with_capacity(8 * dimension); 8 * dimension];
-    solution
-        .into_iter()
-        .enumerate()
-        .for_each(|(major_row, tile_row)| {
-            tile_row.into_iter().for_each(|tile| {
-                tile.variants[tile.variant.unwrap()]
-                    .contents
-                    .iter()
+/// The stitched image in the orientation with the most matches, with matched
+/// pixels drawn as 'O' the way the puzzle shows sea monsters.
+pub fn highlighted(assembled: &Assembled, pattern: &str) -> Vec<Vec<char>> {
+    let image = stitch(&assembled.0);
+    let template = Template::parse(pattern);
+    let (transform, matches) = search_image(&image, &template);
+    let image = transform.apply(&image);
+    let marked = mark_matches(&image, &template, &matches);
+    image
+        .iter()
+        .zip(marked)
+        .map(|(row, marked_row)| {
+            row.iter()
+                .zip(marked_row)
+                .map(|(pixel, marked)| if marked { 'O' } else { pixel.char() })
+                .collect()
+        })
+        .collect()
+}
+
+fn mark_matches(
+    image: &[Vec<Pixel>],
+    template: &Template,
+    matches: &[(usize, usize)],
+) -> Vec<Vec<bool>> {
+    let mut marked: Vec<Vec<bool>> = image.iter().map(|row| vec![false; row.len()]).collect();
+    matches.iter().for_each(|&(row, column)| {
+        template
+            .cells
+            .iter()
+            .for_each(|&(r, c)| marked[row + r][column + c] = true)
+    });
+    marked
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    height: usize,
+    width: usize,
+    cells: Vec<(usize, usize)>,
+}
+
+impl Template {
+    // '#' marks a pixel that has to be on, anything else matches any pixel
+    fn parse(pattern: &str) -> Self {
+        let lines: Vec<&str> = pattern.lines().collect();
+        let cells = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(r, line)| {
+                line.chars()
                     .enumerate()
-                    .for_each(|(tile_row, tiles)| {
-                        image[major_row * 8 + tile_row].extend(tiles.iter());
-                    })
+                    .filter(|&(_, c)| c == '#')
+                    .map(move |(c, _)| (r, c))
             })
-        });
+            .collect();
+        Self {
+            height: lines.len(),
+            width: lines
+                .iter()
+                .map(|line| line.chars().count())
+                .max()
+                .unwrap_or(0),
+            cells,
+        }
+    }
+
+    fn find(&self, image: &[Vec<Pixel>]) -> Vec<(usize, usize)> {
+        let height = image.len();
+        let width = image.first().map_or(0, |row| row.len());
+        if self.cells.is_empty() || self.height > height || self.width > width {
+            return Vec::new();
+        }
+        (0..=height - self.height)
+            .flat_map(|row| (0..=width - self.width).map(move |column| (row, column)))
+            .filter(|&(row, column)| {
+                self.cells
+                    .iter()
+                    .all(|&(r, c)| image[row + r][column + c] == Pixel::On)
+            })
+            .collect()
+    }
+}
 
-    // count pixels that are on
-    let on_count: usize = image
+fn stitch(solution: &Image) -> Vec<Vec<Pixel>> {
+    solution
         .iter()
-        .flat_map(|r| r.iter())
-        .map(|pixel| match pixel {
-            Pixel::On => 1,
-            _ => 0,
-        })
-        .sum();
-
-    // create all rotations/reflections of image
-    let len = image.len();
-    let mut images = vec![image];
-    (0..3).for_each(|_| {
-        let old_image = images.last().unwrap();
-        let mut new_image = vec![Vec::with_capacity(len); len];
-        (0..len).for_each(|x| {
-            (0..len).for_each(|y| {
-                new_image[x].push(old_image[len - y - 1][x]);
+        .flat_map(|tile_row| {
+            let interior = tile_row[0].placed().contents.len();
+            (0..interior).map(move |row| {
+                tile_row
+                    .iter()
+                    .flat_map(|tile| tile.placed().contents[row].iter().copied())
+                    .collect()
             })
-        });
-        images.push(new_image)
-    });
-    (0..4).for_each(|i| {
-        images.push(images[i].iter().cloned().rev().collect());
-    });
+        })
+        .collect()
+}
 
-    // detect monsters
-    for image in images {
-        let mut monster_tiles = 0;
-        image.windows(3).for_each(|w| {
-            w[0].windows(20)
-                .zip(w[1].windows(20).zip(w[2].windows(20)))
-                .for_each(|(top_row, (middle_row, bottom_row))| {
-                    if top_row[18] == Pixel::On
-                        && middle_row[0] == Pixel::On
-                        && middle_row[5] == Pixel::On
-                        && middle_row[6] == Pixel::On
-                        && middle_row[11] == Pixel::On
-                        && middle_row[12] == Pixel::On
-                        && middle_row[17] == Pixel::On
-                        && middle_row[18] == Pixel::On
-                        && middle_row[19] == Pixel::On
-                        && bottom_row[1] == Pixel::On
-                        && bottom_row[4] == Pixel::On
-                        && bottom_row[7] == Pixel::On
-                        && bottom_row[10] == Pixel::On
-                        && bottom_row[13] == Pixel::On
-                        && bottom_row[16] == Pixel::On
-                    {
-                        monster_tiles += 15;
-                    }
-                })
-        });
-        if monster_tiles > 0 {
-            return (on_count - monster_tiles).to_string();
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AssemblyError {
+    NoTiles,
+    NonSquareTile(usize),
+    TileTooSmall {
+        id: usize,
+        size: usize,
+    },
+    MixedTileSizes {
+        id: usize,
+        size: usize,
+        expected: usize,
+    },
+    NoArrangement(usize),
+}
+
+impl Display for AssemblyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoTiles => write!(f, "input contains no tiles"),
+            Self::NonSquareTile(id) => write!(f, "tile {id} is not square"),
+            Self::TileTooSmall { id, size } => write!(
+                f,
+                "tile {id} is {size}x{size}, but tiles need to be at least 3x3 to have an inside"
+            ),
+            Self::MixedTileSizes { id, size, expected } => write!(
+                f,
+                "tile {id} is {size}x{size}, but the first tile is {expected}x{expected}"
+            ),
+            Self::NoArrangement(count) => {
+                write!(f, "{count} tiles cannot be arranged into a rectangle")
+            }
         }
     }
+}
+
+fn load_tiles(input: &str) -> Result<HashMap<usize, Tile>, AssemblyError> {
+    let (_, tiles_raw) = parse_input(input).unwrap();
+    let expected = tiles_raw.first().ok_or(AssemblyError::NoTiles)?.1.len();
+    for (id, v) in &tiles_raw {
+        if v.iter().any(|row| row.len() != v.len()) {
+            return Err(AssemblyError::NonSquareTile(*id));
+        }
+        if v.len() < 3 {
+            return Err(AssemblyError::TileTooSmall {
+                id: *id,
+                size: v.len(),
+            });
+        }
+        if v.len() != expected {
+            return Err(AssemblyError::MixedTileSizes {
+                id: *id,
+                size: v.len(),
+                expected,
+            });
+        }
+    }
+    Ok(tiles_raw
+        .into_iter()
+        .map(|(id, v)| (id, Tile::new(id, v)))
+        .collect())
+}
+
+fn assemble(tiles: &mut HashMap<usize, Tile>) -> Result<Image, AssemblyError> {
+    if let Some(solution) = assemble_greedy(tiles) {
+        return Ok(solution);
+    }
+    assemble_backtracking(tiles)
+}
+
+// Tries every rectangle the tiles could make, the most square ones first.
+fn assemble_backtracking(tiles: &mut HashMap<usize, Tile>) -> Result<Image, AssemblyError> {
+    let count = tiles.len();
+    let mut widths: Vec<usize> = (1..=count)
+        .filter(|&width| count.is_multiple_of(width))
+        .collect();
+    widths.sort_by_key(|&width| width.abs_diff(count / width));
+    for width in widths {
+        let mut solution = Vec::new();
+        if solve(&mut solution, tiles, width) {
+            return Ok(solution);
+        }
+    }
+    Err(AssemblyError::NoArrangement(count))
+}
+
+// Every edge is indexed by its canonical signature (the smaller of the edge and
+// its reverse), so tiles that can sit next to each other share an entry.
+struct EdgeIndex {
+    tiles: HashMap<Vec<Pixel>, Vec<usize>>,
+}
+
+impl EdgeIndex {
+    fn new(tiles: &HashMap<usize, Tile>) -> Self {
+        let mut index: HashMap<Vec<Pixel>, Vec<usize>> = HashMap::new();
+        tiles.values().for_each(|tile| {
+            tile.variants[0]
+                .edges()
+                .into_iter()
+                .for_each(|edge| index.entry(canonical(edge)).or_default().push(tile.id))
+        });
+        Self { tiles: index }
+    }
+
+    fn is_unmatched(&self, edge: &[Pixel]) -> bool {
+        self.tiles[&canonical(edge)].len() == 1
+    }
+
+    fn neighbours(&self, edge: &[Pixel], id: usize) -> Vec<usize> {
+        self.tiles
+            .get(&canonical(edge))
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|&other| other != id)
+            .collect()
+    }
+}
 
-    "NOT FOUND".to_string()
+fn canonical(edge: &[Pixel]) -> Vec<Pixel> {
+    let reversed: Vec<Pixel> = edge.iter().rev().copied().collect();
+    reversed.min(edge.to_vec())
 }
 
-fn solve(solution: &mut Image, unused_tiles: &mut HashMap<usize, Tile>, dimension: usize) -> bool {
+// Starts from a corner (the tile with the most unmatched edges) and fills the
+// grid row by row, only looking at tiles that share the edge of the neighbour to
+// the left or above. The first row ends at the first unmatched right edge, which
+// fixes the width. Gives up, leaving `tiles` untouched, as soon as a slot can't
+// be filled.
+fn assemble_greedy(tiles: &mut HashMap<usize, Tile>) -> Option<Image> {
+    let index = EdgeIndex::new(tiles);
+    let count = tiles.len();
+    let corner = tiles
+        .values()
+        .map(|tile| {
+            let unmatched = tile.variants[0]
+                .edges()
+                .into_iter()
+                .filter(|edge| index.is_unmatched(edge))
+                .count();
+            (unmatched, std::cmp::Reverse(tile.id))
+        })
+        .max()
+        .filter(|&(unmatched, _)| unmatched >= 2)?
+        .1
+         .0;
+    let mut solution: Image = Vec::new();
+    let mut width = None;
+    while !tiles.is_empty() {
+        let row = solution.len();
+        let mut tile_row: Vec<Tile> = Vec::new();
+        while width.is_none_or(|width| tile_row.len() < width) {
+            let column = tile_row.len();
+            let above = row.checked_sub(1).map(|r| solution[r][column].placed());
+            let left = column.checked_sub(1).map(|c| tile_row[c].placed());
+            let candidates = match (above, left) {
+                (None, None) => vec![corner],
+                (_, Some(left)) => index.neighbours(&left.right, tile_row[column - 1].id),
+                (Some(above), None) => index.neighbours(&above.bottom, solution[row - 1][0].id),
+            };
+            let found = candidates
+                .into_iter()
+                .filter(|id| tiles.contains_key(id))
+                .find_map(|id| {
+                    let fitting = D4::ALL.into_iter().find(|transform| {
+                        let variant = &tiles[&id].variants[transform.index()];
+                        let fits_above = match above {
+                            Some(above) => above.bottom == variant.top,
+                            None => index.is_unmatched(&variant.top),
+                        };
+                        let fits_left = match left {
+                            Some(left) => left.right == variant.left,
+                            None => index.is_unmatched(&variant.left),
+                        };
+                        fits_above && fits_left
+                    });
+                    fitting.map(|transform| (id, transform))
+                });
+            let Some((id, transform)) = found else {
+                solution.push(tile_row);
+                solution.into_iter().flatten().for_each(|mut tile| {
+                    tile.transform = None;
+                    tiles.insert(tile.id, tile);
+                });
+                return None;
+            };
+            let mut tile = tiles.remove(&id).unwrap();
+            tile.transform = Some(transform);
+            let at_end = index.is_unmatched(&tile.placed().right);
+            tile_row.push(tile);
+            if width.is_none() && at_end {
+                width = Some(tile_row.len());
+            }
+        }
+        solution.push(tile_row);
+    }
+    if !count.is_multiple_of(solution[0].len()) {
+        return None;
+    }
+    Some(solution)
+}
+
+fn solve(solution: &mut Image, unused_tiles: &mut HashMap<usize, Tile>, width: usize) -> bool {
     if unused_tiles.is_empty() {
         return true;
     }
     let current_column = if let Some(row) = solution.last() {
-        if row.len() == dimension {
+        if row.len() == width {
             solution.push(Vec::new());
             0
         } else {
@@ -144,16 +490,14 @@ fn solve(solution: &mut Image, unused_tiles: &mut HashMap<usize, Tile>, dimensio
         let mut tile = unused_tiles
             .remove(&id)
             .expect("Tile not yet tried should be in map!");
-        for variant_index in 0..tile.variants.len() {
+        for transform in D4::ALL {
             if current_row > 0 {
                 let tile_above = solution
                     .get(current_row - 1)
                     .unwrap()
                     .get(current_column)
                     .unwrap();
-                if tile_above.variants[tile_above.variant.unwrap()].bottom
-                    != tile.variants[variant_index].top
-                {
+                if tile_above.placed().bottom != tile.variants[transform.index()].top {
                     continue;
                 }
             }
@@ -164,24 +508,22 @@ fn solve(solution: &mut Image, unused_tiles: &mut HashMap<usize, Tile>, dimensio
                     .unwrap()
                     .get(current_column - 1)
                     .unwrap();
-                if tile_to_left.variants[tile_to_left.variant.unwrap()].right
-                    != tile.variants[variant_index].left
-                {
+                if tile_to_left.placed().right != tile.variants[transform.index()].left {
                     continue;
                 }
             }
             // potential match!
-            let _ = tile.variant.insert(variant_index);
+            let _ = tile.transform.insert(transform);
             solution.last_mut().unwrap().push(tile.clone());
-            if solve(solution, unused_tiles, dimension) {
+            if solve(solution, unused_tiles, width) {
                 return true;
             } else {
                 solution.last_mut().unwrap().pop().unwrap();
-                tile.variant.take();
+                tile.transform.take();
             }
         }
         // println!("Tried all variants of {id}");
-        tile.variant.take();
+        tile.transform.take();
         unused_tiles.insert(id, tile);
     }
     if solution.last().unwrap().is_empty() {
@@ -192,7 +534,87 @@ fn solve(solution: &mut Image, unused_tiles: &mut HashMap<usize, Tile>, dimensio
 
 type Image = Vec<Vec<Tile>>;
 
+/// One of the eight symmetries of a square: `rotation` clockwise quarter turns,
+/// applied after reversing the order of the rows if `flipped`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct D4 {
+    rotation: u8,
+    flipped: bool,
+}
+
+impl D4 {
+    pub const IDENTITY: Self = Self::new(0, false);
+
+    pub const ALL: [Self; 8] = [
+        Self::new(0, false),
+        Self::new(1, false),
+        Self::new(2, false),
+        Self::new(3, false),
+        Self::new(0, true),
+        Self::new(1, true),
+        Self::new(2, true),
+        Self::new(3, true),
+    ];
+
+    pub const fn new(rotation: u8, flipped: bool) -> Self {
+        Self {
+            rotation: rotation % 4,
+            flipped,
+        }
+    }
+
+    pub fn rotation(&self) -> u8 {
+        self.rotation
+    }
+
+    pub fn flipped(&self) -> bool {
+        self.flipped
+    }
+
+    // position in `ALL`
+    fn index(&self) -> usize {
+        self.rotation as usize + if self.flipped { 4 } else { 0 }
+    }
+
+    /// The transform that applies `self` first and then `other`.
+    pub fn then(&self, other: Self) -> Self {
+        // a flip turns every rotation before it the other way around
+        if other.flipped {
+            Self::new(other.rotation + 4 - self.rotation, !self.flipped)
+        } else {
+            Self::new(other.rotation + self.rotation, self.flipped)
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        if self.flipped {
+            *self
+        } else {
+            Self::new(4 - self.rotation, false)
+        }
+    }
+
+    /// Transforms a grid of any shape; rotating swaps its width and height.
+    pub fn apply<T: Clone>(&self, grid: &[Vec<T>]) -> Vec<Vec<T>> {
+        let mut grid = grid.to_vec();
+        if self.flipped {
+            grid.reverse();
+        }
+        (0..self.rotation).for_each(|_| {
+            let (height, width) = (grid.len(), grid.first().map_or(0, |row| row.len()));
+            grid = (0..width)
+                .map(|x| {
+                    (0..height)
+                        .map(|y| grid[height - y - 1][x].clone())
+                        .collect()
+                })
+                .collect();
+        });
+        grid
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum Pixel {
     On,
     Off,
@@ -211,7 +633,7 @@ impl Pixel {
 struct Tile {
     id: usize,
     variants: Vec<TileVariant>,
-    variant: Option<usize>,
+    transform: Option<D4>,
 }
 
 impl Debug for Tile {
@@ -221,18 +643,30 @@ impl Debug for Tile {
 }
 
 impl Tile {
+    fn placed(&self) -> &TileVariant {
+        &self.variants[self.transform.unwrap().index()]
+    }
+
     fn new(id: usize, v: Vec<Vec<char>>) -> Self {
-        let mut variants = vec![TileVariant::new(v)];
-        (0..3).for_each(|_| {
-            variants.push(variants.last().unwrap().rotate());
-        });
-        (0..3).for_each(|i| {
-            variants.push(variants[i].flip_vertical());
-        });
+        let pixels: Vec<Vec<Pixel>> = v
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|cell| match cell {
+                        '.' => Pixel::Off,
+                        '#' => Pixel::On,
+                        _ => unreachable!(),
+                    })
+                    .collect()
+            })
+            .collect();
         Self {
             id,
-            variants,
-            variant: None,
+            variants: D4::ALL
+                .iter()
+                .map(|transform| TileVariant::new(transform.apply(&pixels)))
+                .collect(),
+            transform: None,
         }
     }
 }
@@ -268,96 +702,23 @@ impl Debug for TileVariant {
 }
 
 impl TileVariant {
-    fn new(v: Vec<Vec<char>>) -> Self {
+    fn new(mut v: Vec<Vec<Pixel>>) -> Self {
         let len = v.len();
+        let left = v.iter().map(|row| row[0]).collect();
+        let right = v.iter().map(|row| row[len - 1]).collect();
+        let bottom = v.pop().unwrap();
+        let top = v.remove(0);
         Self {
-            top: v[0]
-                .iter()
-                .map(|cell| match cell {
-                    '.' => Pixel::Off,
-                    '#' => Pixel::On,
-                    _ => unreachable!(),
-                })
-                .collect(),
-            bottom: v
-                .last()
-                .unwrap()
-                .iter()
-                .map(|cell| match cell {
-                    '.' => Pixel::Off,
-                    '#' => Pixel::On,
-                    _ => unreachable!(),
-                })
-                .collect(),
-            left: v
-                .iter()
-                .map(|vc| match vc[0] {
-                    '.' => Pixel::Off,
-                    '#' => Pixel::On,
-                    _ => unreachable!(),
-                })
-                .collect(),
-            right: v
-                .iter()
-                .map(|vc| match vc.last().unwrap() {
-                    '.' => Pixel::Off,
-                    '#' => Pixel::On,
-                    _ => unreachable!(),
-                })
-                .collect(),
-            contents: v
-                .into_iter()
-                .skip(1)
-                .take(len - 2)
-                .map(|row| {
-                    row.into_iter()
-                        .skip(1)
-                        .take(len - 2)
-                        .map(|pixel| match pixel {
-                            '.' => Pixel::Off,
-                            '#' => Pixel::On,
-                            _ => unreachable!(),
-                        })
-                        .collect()
-                })
-                .collect(),
+            top,
+            bottom,
+            left,
+            right,
+            contents: v.into_iter().map(|row| row[1..len - 1].to_vec()).collect(),
         }
     }
 
-    fn rotate(&self) -> Self {
-        let len = self.contents.len();
-        let mut contents = vec![Vec::with_capacity(len); len];
-        (0..len).for_each(|x| {
-            (0..len).for_each(|y| {
-                contents[x].push(self.contents[len - y - 1][x]);
-            })
-        });
-        Self {
-            top: self.left.iter().cloned().rev().collect(),
-            bottom: self.right.iter().cloned().rev().collect(),
-            left: self.bottom.clone(),
-            right: self.top.clone(),
-            contents,
-        }
-    }
-
-    // fn flip_horizontal(&self) -> Self {
-    //     Self {
-    //         top: self.top.iter().cloned().rev().collect(),
-    //         bottom: self.bottom.iter().cloned().rev().collect(),
-    //         left: self.right.clone(),
-    //         right: self.left.clone(),
-    //     }
-    // }
-
-    fn flip_vertical(&self) -> Self {
-        Self {
-            top: self.bottom.clone(),
-            bottom: self.top.clone(),
-            left: self.left.iter().cloned().rev().collect(),
-            right: self.right.iter().cloned().rev().collect(),
-            contents: self.contents.iter().cloned().rev().collect(),
-        }
+    fn edges(&self) -> [&Vec<Pixel>; 4] {
+        [&self.top, &self.right, &self.bottom, &self.left]
     }
 
     #[allow(dead_code)]
@@ -371,6 +732,61 @@ impl TileVariant {
     }
 }
 
+pub fn synthetic_input(seed: u64, width: usize, height: usize, tile_size: usize) -> String {
+    let mut rng = Rng(seed);
+    let pixels: Vec<Vec<char>> = (0..height * (tile_size - 1) + 1)
+        .map(|_| {
+            (0..width * (tile_size - 1) + 1)
+                .map(|_| if rng.next() & 1 == 1 { '#' } else { '.' })
+                .collect()
+        })
+        .collect();
+    let mut ids: Vec<usize> = (1000..1000 + width * height).collect();
+    for i in (1..ids.len()).rev() {
+        ids.swap(i, rng.below(i + 1));
+    }
+    let mut tiles: Vec<String> = (0..width * height)
+        .map(|i| {
+            let (row, column) = (i / width, i % width);
+            let mut tile: Vec<Vec<char>> = pixels[row * (tile_size - 1)..][..tile_size]
+                .iter()
+                .map(|r| r[column * (tile_size - 1)..][..tile_size].to_vec())
+                .collect();
+            (0..rng.below(4)).for_each(|_| {
+                tile = (0..tile_size)
+                    .map(|x| (0..tile_size).map(|y| tile[tile_size - y - 1][x]).collect())
+                    .collect();
+            });
+            if rng.below(2) == 1 {
+                tile.reverse();
+            }
+            let rows: Vec<String> = tile.into_iter().map(|r| r.into_iter().collect()).collect();
+            format!("Tile {}:\n{}", ids[i], rows.join("\n"))
+        })
+        .collect();
+    for i in (1..tiles.len()).rev() {
+        tiles.swap(i, rng.below(i + 1));
+    }
+    tiles.join("\n\n")
+}
+
+// splitmix64, so synthetic puzzles only depend on the seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
 type TileVec = (usize, Vec<Vec<char>>);
 
 fn parse_input(input: &str) -> IResult<&str, Vec<TileVec>> {
@@ -614,5 +1030,156 @@ Tile 3079:
 ..#.###...";
         let result = process_part2(input);
         assert_eq!(result, "273");
+        let assembled = assemble_input(input);
+        let (transform, matches) = find_pattern(&assembled, SEA_MONSTER);
+        assert_eq!(matches.len(), 2);
+        let image = transform.apply(&stitched_image(&assembled));
+        let template = Template::parse(SEA_MONSTER);
+        assert!(matches.iter().all(|&(row, column)| template
+            .cells
+            .iter()
+            .all(|&(r, c)| image[row + r][column + c] == '#')));
+        let grid = tile_grid(&assembled);
+        assert_eq!(grid.lines().count(), 3);
+        let corners = [1951, 3079, 2971, 1171];
+        let ids: Vec<usize> = grid
+            .split_whitespace()
+            .map(|id| id.parse().unwrap())
+            .collect();
+        assert!([ids[0], ids[2], ids[6], ids[8]]
+            .iter()
+            .all(|id| corners.contains(id)));
+        let pixels = highlighted(&assembled, SEA_MONSTER);
+        let rendered = render_image(&pixels);
+        assert_eq!(rendered.chars().filter(|&c| c == 'O').count(), 30);
+        assert_eq!(rendered.chars().filter(|&c| c == '#').count(), 273);
+        assert!(rendered.contains(".####...#####..#...###.."));
+        assert!(rendered.contains("#.O.##.OO#.#.OO.##.OOO##"));
+        let png = render_png(&pixels, 2).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[16..24], &[0, 0, 0, 48, 0, 0, 0, 48]);
+        assert_eq!(render_png(&pixels, 0), Err(RenderError::ZeroScale));
+        assert_eq!(render_png(&[], 2), Err(RenderError::EmptyImage));
+        assert_eq!(process_part2_with(input, "#"), "0");
+        assert_eq!(
+            process_part2_with(
+                input,
+                "#\n.\n#\n.\n#\n.\n#\n.\n#\n.\n#\n.\n#\n.\n#\n.\n#\n.\n#\n.\n#\n.\n#\n.\n#"
+            ),
+            "NOT FOUND"
+        );
+    }
+
+    #[test]
+    fn templates() {
+        let image: Vec<Vec<Pixel>> = ["####.", "####.", "....#"]
+            .iter()
+            .map(|row| {
+                row.chars()
+                    .map(|c| if c == '#' { Pixel::On } else { Pixel::Off })
+                    .collect()
+            })
+            .collect();
+        let template = Template::parse("##\n.#");
+        assert_eq!((template.height, template.width), (2, 2));
+        let matches = template.find(&image);
+        assert_eq!(matches, vec![(0, 0), (0, 1), (0, 2)]);
+        let marked = mark_matches(&image, &template, &matches);
+        assert_eq!(marked.iter().flatten().filter(|&&m| m).count(), 7);
+        assert!(Template::parse("#\n#\n#\n#").find(&image).is_empty());
+        assert_eq!(Template::parse(SEA_MONSTER).cells.len(), 15);
+    }
+
+    #[test]
+    fn transforms() {
+        let grid = vec![vec![1, 2, 3], vec![4, 5, 6]];
+        assert_eq!(D4::IDENTITY.apply(&grid), grid);
+        assert_eq!(D4::new(1, false).apply(&grid), [[4, 1], [5, 2], [6, 3]]);
+        assert_eq!(D4::new(0, true).apply(&grid), [[4, 5, 6], [1, 2, 3]]);
+        assert_eq!(D4::new(5, false), D4::new(1, false));
+        let mut images: Vec<Vec<Vec<i32>>> = D4::ALL.iter().map(|t| t.apply(&grid)).collect();
+        images.sort();
+        images.dedup();
+        assert_eq!(images.len(), 8);
+        for a in D4::ALL {
+            assert_eq!(a.then(a.inverse()), D4::IDENTITY);
+            assert_eq!(a.inverse().then(a), D4::IDENTITY);
+            assert_eq!(a.inverse().apply(&a.apply(&grid)), grid);
+            for b in D4::ALL {
+                assert_eq!(a.then(b).apply(&grid), b.apply(&a.apply(&grid)));
+            }
+        }
+
+        // tile variants follow the same transforms as the grids they came from
+        let tiles = load_tiles(&synthetic_input(25, 1, 1, 10)).unwrap();
+        let tile = tiles.values().next().unwrap();
+        let quarter_turn = D4::new(1, false);
+        for t in D4::ALL {
+            let before = &tile.variants[t.index()];
+            let after = &tile.variants[t.then(quarter_turn).index()];
+            assert_eq!(
+                after.top,
+                before.left.iter().rev().copied().collect::<Vec<_>>()
+            );
+            assert_eq!(after.right, before.top);
+            assert_eq!(after.contents, quarter_turn.apply(&before.contents));
+        }
+    }
+
+    #[test]
+    fn synthetic() {
+        let input = synthetic_input(20, 8, 8, 10);
+        let (_, tiles_raw) = parse_input(&input).unwrap();
+        assert_eq!(tiles_raw.len(), 64);
+        assert_eq!(process_part1(&input), process_part1_backtracking(&input));
+        let input = synthetic_input(21, 30, 30, 24);
+        let mut tiles = load_tiles(&input).unwrap();
+        assert!(assemble_greedy(&mut tiles).is_some());
+        assert!(tiles.is_empty());
+    }
+
+    #[test]
+    fn rectangular() {
+        for (width, height) in [(5, 3), (3, 5), (7, 1), (1, 4), (1, 1)] {
+            let input = synthetic_input(22, width, height, 24);
+            let mut tiles = load_tiles(&input).unwrap();
+            let solution = assemble_greedy(&mut tiles).unwrap();
+            let (rows, columns) = (solution.len(), solution[0].len());
+            assert!((rows, columns) == (height, width) || (rows, columns) == (width, height));
+            let image = stitch(&solution);
+            assert_eq!(image.len(), rows * 22);
+            assert!(image.iter().all(|row| row.len() == columns * 22));
+            assert_eq!(process_part1(&input), process_part1_backtracking(&input));
+        }
+    }
+
+    #[test]
+    fn tile_errors() {
+        assert_eq!(
+            load_tiles("Tile 1:\n...\n...\n...\n\nTile 2:\n....\n....\n....\n....").unwrap_err(),
+            AssemblyError::MixedTileSizes {
+                id: 2,
+                size: 4,
+                expected: 3
+            }
+        );
+        assert_eq!(
+            load_tiles("Tile 1:\n....\n....\n....").unwrap_err(),
+            AssemblyError::NonSquareTile(1)
+        );
+        assert_eq!(
+            load_tiles("Tile 1:\n..\n..").unwrap_err(),
+            AssemblyError::TileTooSmall { id: 1, size: 2 }
+        );
+        let mut tiles = load_tiles(&synthetic_input(23, 1, 1, 10)).unwrap();
+        let stray = load_tiles(&synthetic_input(24, 1, 1, 10))
+            .unwrap()
+            .remove(&1000)
+            .unwrap();
+        tiles.insert(1001, Tile { id: 1001, ..stray });
+        assert_eq!(
+            assemble(&mut tiles).unwrap_err(),
+            AssemblyError::NoArrangement(2)
+        );
     }
 }
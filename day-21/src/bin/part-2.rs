@@ -1,7 +1,11 @@
-use answer::process_part2;
-use std::fs;
+use answer::{explain, process_part2};
+use std::{env, fs};
 
 fn main() {
     let file = fs::read_to_string("./input.txt").unwrap();
+    if env::args().skip(1).any(|arg| arg == "--explain") {
+        println!("{}", explain(&file));
+        return;
+    }
     println!("{}", process_part2(&file));
 }
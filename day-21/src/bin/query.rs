@@ -0,0 +1,47 @@
+use answer::FoodList;
+use std::{env, fs, process};
+
+const USAGE: &str =
+    "usage: query safe | counts | safe-foods | unresolved | containing <ingredient>";
+
+fn usage(message: &str) -> ! {
+    eprintln!("{message}\n{USAGE}");
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let file = fs::read_to_string("./input.txt").unwrap();
+    let food_list = FoodList::parse(&file);
+    let lines: Vec<String> = match args.first().map(String::as_str) {
+        Some("safe") => food_list
+            .safe_ingredients()
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        Some("counts") => food_list
+            .safe_counts()
+            .into_iter()
+            .map(|(ingredient, count)| format!("{ingredient} {count}"))
+            .collect(),
+        Some("safe-foods") => food_list
+            .safe_foods()
+            .into_iter()
+            .map(|line| line.to_string())
+            .collect(),
+        Some("unresolved") => food_list.unresolved_allergens(),
+        Some("containing") => {
+            let ingredient = args
+                .get(1)
+                .unwrap_or_else(|| usage("Missing ingredient for containing"));
+            food_list
+                .foods_containing(ingredient)
+                .into_iter()
+                .map(|line| line.to_string())
+                .collect()
+        }
+        Some(other) => usage(&format!("Unknown query: {other}")),
+        None => usage("Missing query"),
+    };
+    lines.iter().for_each(|line| println!("{line}"));
+}
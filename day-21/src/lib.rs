@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Display,
+};
 
 use itertools::Itertools;
 
@@ -6,114 +9,298 @@ use nom::{
     bytes::complete::tag,
     character::complete::char as nomchar,
     character::complete::{alpha1, newline, space1},
+    combinator::opt,
     multi::separated_list1,
-    sequence::terminated,
+    sequence::delimited,
     IResult,
 };
 
 pub fn process_part1(input: &str) -> String {
-    let (_, parse_result) = parse_input(input).unwrap();
-    let (_, allergens) = find_allergens(&parse_result);
-    parse_result
-        .into_iter()
-        .flat_map(|(foods, _)| foods.into_iter())
-        .filter(|&food| !allergens.contains(food))
-        .count()
+    FoodList::parse(input)
+        .safe_counts()
+        .values()
+        .sum::<usize>()
         .to_string()
 }
 
 pub fn process_part2(input: &str) -> String {
-    let (_, parse_result) = parse_input(input).unwrap();
-    let (allergen_map, _) = find_allergens(&parse_result);
-    let mut list: Vec<_> = allergen_map
-        .into_iter()
-        .map(|(k, v)| (k, v.into_iter().next().unwrap()))
-        .collect();
-    list.sort_by(|a, b| a.0.cmp(b.0));
-    let mut answer = String::new();
-    Itertools::intersperse(list.into_iter().map(|(_allergen, food)| food), ",")
-        .for_each(|s| answer.push_str(s));
-    answer
+    let allergens = FoodList::parse(input)
+        .allergens()
+        .unwrap_or_else(|e| panic!("{e}"));
+    allergens.values().join(",")
 }
 
-fn find_allergens<'a>(
-    parse_result: &[(Vec<&'a str>, Vec<&'a str>)],
-) -> (HashMap<&'a str, HashSet<&'a str>>, HashSet<&'a str>) {
-    let allergen_sets: Vec<_> = parse_result
-        .iter()
-        .flat_map(|(foods, allergens)| {
-            let set = HashSet::<_>::from_iter(foods.iter().cloned());
-            allergens
-                .iter()
-                .map(move |&allergen| (allergen, set.clone()))
-        })
-        .collect();
-    let mut allergen_map = HashMap::new();
-    allergen_sets.into_iter().for_each(|(allergen, foods)| {
-        allergen_map
-            .entry(allergen)
-            .or_insert_with(Vec::new)
-            .push(foods);
-    });
-    let mut allergen_map: HashMap<&str, HashSet<&str>> = allergen_map
-        .into_iter()
-        .map(|(k, v)| {
-            let first = v.first().unwrap().clone();
-            (
-                k,
-                v.into_iter()
-                    .fold(first, |acc, el| acc.intersection(&el).copied().collect()),
-            )
-        })
-        .collect();
+/// The parsed food lines. Foods are referred to by their 1-based line number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoodList<'a> {
+    lines: Vec<Line<'a>>,
+}
+
+impl<'a> FoodList<'a> {
+    pub fn parse(input: &'a str) -> Self {
+        let (_, lines) = parse_input(input).unwrap();
+        Self { lines }
+    }
+
+    /// Every allergen with the ingredient containing it, sorted by allergen.
+    pub fn allergens(&self) -> Result<BTreeMap<&'a str, &'a str>, AllergenError> {
+        find_allergens(&self.lines)
+    }
 
-    let mut done = false;
-    let mut singles = HashSet::new();
-    while !done {
-        done = true;
-        allergen_map
+    /// Ingredients that can't contain any allergen.
+    pub fn safe_ingredients(&self) -> BTreeSet<&'a str> {
+        // an ingredient that isn't a candidate for any allergen can't contain one
+        let candidates: BTreeSet<&str> = allergen_candidates(&self.lines)
+            .into_values()
+            .flatten()
+            .collect();
+        self.lines
             .iter()
-            .filter(|(_k, v)| v.len() == 1)
-            .flat_map(|(_k, v)| v.iter().copied())
-            .for_each(|food| {
-                singles.insert(food);
-            });
-        singles.drain().for_each(|food| {
-            allergen_map.values_mut().for_each(|foods| {
-                if foods.len() > 1 {
-                    let _ = foods.remove(food);
-                    if foods.len() > 1 {
-                        done = false;
-                    }
+            .flat_map(|(ingredients, _)| ingredients.iter().copied())
+            .filter(|ingredient| !candidates.contains(ingredient))
+            .collect()
+    }
+
+    /// How many foods every safe ingredient appears in.
+    pub fn safe_counts(&self) -> BTreeMap<&'a str, usize> {
+        let safe = self.safe_ingredients();
+        let mut counts = BTreeMap::new();
+        self.lines
+            .iter()
+            .flat_map(|(ingredients, _)| ingredients.iter())
+            .filter(|ingredient| safe.contains(*ingredient))
+            .for_each(|&ingredient| *counts.entry(ingredient).or_insert(0) += 1);
+        counts
+    }
+
+    /// Foods made only of safe ingredients.
+    pub fn safe_foods(&self) -> Vec<usize> {
+        let safe = self.safe_ingredients();
+        self.foods_where(|(ingredients, _)| ingredients.iter().all(|i| safe.contains(i)))
+    }
+
+    /// Allergens whose ingredient can't be pinned down: those left out of some
+    /// largest matching, or given a different ingredient by one.
+    pub fn unresolved_allergens(&self) -> Vec<String> {
+        let (allergens, options) = allergen_options(&self.lines);
+        let matching = match_allergens(&options, None);
+        let size = matching.iter().flatten().count();
+        (0..allergens.len())
+            .filter(|&i| match matching[i] {
+                None => true,
+                Some(ingredient) => {
+                    let banned = match_allergens(&options, Some((i, ingredient)));
+                    banned.iter().flatten().count() == size
                 }
             })
+            .map(|i| allergens[i].to_string())
+            .collect()
+    }
+
+    pub fn foods_containing(&self, ingredient: &str) -> Vec<usize> {
+        self.foods_where(|(ingredients, _)| ingredients.contains(&ingredient))
+    }
+
+    fn foods_where(&self, predicate: impl Fn(&Line) -> bool) -> Vec<usize> {
+        (0..self.lines.len())
+            .filter(|&i| predicate(&self.lines[i]))
+            .map(|i| i + 1)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllergenError {
+    NoCandidates(String),
+    Unsatisfiable(Vec<String>),
+    Ambiguous(Vec<String>),
+}
+
+impl Display for AllergenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoCandidates(allergen) => write!(
+                f,
+                "No ingredient appears in every food containing {allergen}"
+            ),
+            Self::Unsatisfiable(allergens) => write!(
+                f,
+                "Not enough ingredients for allergens {}",
+                allergens.join(", ")
+            ),
+            Self::Ambiguous(allergens) => write!(
+                f,
+                "More than one ingredient fits allergens {}",
+                allergens.join(", ")
+            ),
+        }
+    }
+}
+
+// Every allergen with the ingredients that appear in all foods listing it.
+fn allergen_candidates<'a>(lines: &[Line<'a>]) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
+    let mut candidates: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    lines.iter().for_each(|(ingredients, allergens)| {
+        let set = BTreeSet::from_iter(ingredients.iter().copied());
+        allergens.iter().for_each(|&allergen| {
+            candidates
+                .entry(allergen)
+                .and_modify(|c| c.retain(|ingredient| set.contains(ingredient)))
+                .or_insert_with(|| set.clone());
         })
+    });
+    candidates
+}
+
+// Assigns every allergen its own ingredient, sorted by allergen. The matching
+// has to be the only one possible: an allergen is ambiguous if banning its
+// ingredient still leaves a complete matching.
+fn find_allergens<'a>(lines: &[Line<'a>]) -> Result<BTreeMap<&'a str, &'a str>, AllergenError> {
+    let (allergens, options) = allergen_options(lines);
+    if let Some(i) = options.iter().position(Vec::is_empty) {
+        return Err(AllergenError::NoCandidates(allergens[i].to_string()));
     }
 
-    let allergens: HashSet<&str> = allergen_map
-        .values()
-        .flat_map(|s| {
-            assert_eq!(s.len(), 1);
-            s.iter().copied()
+    let matching = match_allergens(&options, None);
+    let unmatched: Vec<String> = (0..allergens.len())
+        .filter(|&i| matching[i].is_none())
+        .map(|i| allergens[i].to_string())
+        .collect();
+    if !unmatched.is_empty() {
+        return Err(AllergenError::Unsatisfiable(unmatched));
+    }
+    let matching: Vec<&str> = matching.into_iter().flatten().collect();
+    let ambiguous: Vec<String> = (0..allergens.len())
+        .filter(|&i| {
+            match_allergens(&options, Some((i, matching[i])))
+                .iter()
+                .all(Option::is_some)
         })
+        .map(|i| allergens[i].to_string())
         .collect();
+    if !ambiguous.is_empty() {
+        return Err(AllergenError::Ambiguous(ambiguous));
+    }
+    Ok(allergens.into_iter().zip(matching).collect())
+}
+
+// The allergens in order, each with its candidate ingredients.
+fn allergen_options<'a>(lines: &[Line<'a>]) -> (Vec<&'a str>, Vec<Vec<&'a str>>) {
+    allergen_candidates(lines)
+        .into_iter()
+        .map(|(allergen, candidates)| (allergen, candidates.into_iter().collect()))
+        .unzip()
+}
 
-    (allergen_map, allergens)
+// Maximum bipartite matching of allergens to ingredients by augmenting paths.
+fn match_allergens<'a>(
+    options: &[Vec<&'a str>],
+    banned: Option<(usize, &'a str)>,
+) -> Vec<Option<&'a str>> {
+    fn augment<'a>(
+        i: usize,
+        options: &[Vec<&'a str>],
+        banned: Option<(usize, &'a str)>,
+        seen: &mut HashSet<&'a str>,
+        owner: &mut HashMap<&'a str, usize>,
+    ) -> bool {
+        for &ingredient in &options[i] {
+            if banned == Some((i, ingredient)) || !seen.insert(ingredient) {
+                continue;
+            }
+            if owner
+                .get(ingredient)
+                .copied()
+                .is_none_or(|j| augment(j, options, banned, seen, owner))
+            {
+                owner.insert(ingredient, i);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut owner = HashMap::new();
+    for i in 0..options.len() {
+        augment(i, options, banned, &mut HashSet::new(), &mut owner);
+    }
+    let mut matching = vec![None; options.len()];
+    owner
+        .into_iter()
+        .for_each(|(ingredient, i)| matching[i] = Some(ingredient));
+    matching
+}
+
+// For every allergen, every ingredient of the foods listing it and why it was
+// ruled out: the (1-based) food lines it's missing from, or the allergen that
+// took it.
+pub fn explain(input: &str) -> String {
+    let lines = FoodList::parse(input).lines;
+    let result = find_allergens(&lines);
+    let assigned = result.clone().unwrap_or_default();
+    let candidates = allergen_candidates(&lines);
+    let mut output: Vec<String> = candidates
+        .iter()
+        .flat_map(|(&allergen, possible)| {
+            let listing: Vec<usize> = (0..lines.len())
+                .filter(|&i| lines[i].1.contains(&allergen))
+                .collect();
+            let header = match assigned.get(allergen) {
+                Some(ingredient) => format!("{allergen}: {ingredient}"),
+                None => format!("{allergen}: unresolved"),
+            };
+            let ingredients: BTreeSet<&str> = listing
+                .iter()
+                .flat_map(|&i| lines[i].0.iter().copied())
+                .collect();
+            let reasons = ingredients
+                .into_iter()
+                .filter(|&ingredient| assigned.get(allergen) != Some(&ingredient))
+                .map(|ingredient| {
+                    let missing: Vec<String> = listing
+                        .iter()
+                        .filter(|&&i| !lines[i].0.contains(&ingredient))
+                        .map(|i| (i + 1).to_string())
+                        .collect();
+                    let reason = match missing.len() {
+                        0 => match assigned.iter().find(|(_, &i)| i == ingredient) {
+                            Some((other, _)) => format!("contains {other}"),
+                            None if possible.contains(ingredient) => "still possible".to_string(),
+                            None => unreachable!(),
+                        },
+                        1 => format!("not on line {}", missing[0]),
+                        _ => format!("not on lines {}", missing.join(", ")),
+                    };
+                    format!("  {ingredient}: {reason}")
+                })
+                .collect::<Vec<_>>();
+            std::iter::once(header).chain(reasons)
+        })
+        .collect();
+    if let Err(e) = result {
+        output.push(e.to_string());
+    }
+    output.join("\n")
 }
 
 // foods, allergens
 type Line<'a> = (Vec<&'a str>, Vec<&'a str>);
 
-fn parse_input(input: &str) -> IResult<&str, Vec<Line>> {
+fn parse_input(input: &str) -> IResult<&str, Vec<Line<'_>>> {
     let (input, lines) = separated_list1(newline, parse_line)(input)?;
     Ok((input, lines))
 }
 
-fn parse_line(input: &str) -> IResult<&str, Line> {
-    let (input, foods) = terminated(separated_list1(space1, alpha1), tag(" (contains "))(input)?;
-    let (input, allergens) = terminated(separated_list1(tag(", "), alpha1), nomchar(')'))(input)?;
+// allergens aren't always marked, so the list is optional
+fn parse_line(input: &str) -> IResult<&str, Line<'_>> {
+    let (input, foods) = separated_list1(space1, alpha1)(input)?;
+    let (input, allergens) = opt(delimited(
+        tag(" (contains "),
+        separated_list1(tag(", "), alpha1),
+        nomchar(')'),
+    ))(input)?;
 
-    Ok((input, (foods, allergens)))
+    Ok((input, (foods, allergens.unwrap_or_default())))
 }
 
 #[cfg(test)]
@@ -139,4 +326,79 @@ sqjhc mxmxvkd sbzzf (contains fish)";
         let result = process_part2(input);
         assert_eq!(result, "mxmxvkd,sqjhc,fvjkl");
     }
+
+    #[test]
+    fn explanation() {
+        let input = "mxmxvkd kfcds sqjhc nhms (contains dairy, fish)
+trh fvjkl sbzzf mxmxvkd (contains dairy)
+sqjhc fvjkl (contains soy)
+sqjhc mxmxvkd sbzzf (contains fish)";
+        let explanation = explain(input);
+        assert!(explanation.starts_with("dairy: mxmxvkd\n  fvjkl: not on line 1\n"));
+        assert!(explanation.contains("fish: sqjhc\n  kfcds: not on line 4\n"));
+        assert!(explanation.contains("  mxmxvkd: contains dairy\n"));
+        assert!(explanation.ends_with("soy: fvjkl\n  sqjhc: contains fish"));
+    }
+
+    #[test]
+    fn bad_data() {
+        let (_, lines) = parse_input("a b (contains x, y)").unwrap();
+        assert_eq!(
+            find_allergens(&lines),
+            Err(AllergenError::Ambiguous(vec![
+                "x".to_string(),
+                "y".to_string()
+            ]))
+        );
+        let (_, lines) = parse_input("a (contains x, y)").unwrap();
+        assert!(matches!(
+            find_allergens(&lines),
+            Err(AllergenError::Unsatisfiable(_))
+        ));
+        let input = "a b (contains x)\nc d (contains x)";
+        let (_, lines) = parse_input(input).unwrap();
+        assert_eq!(
+            find_allergens(&lines),
+            Err(AllergenError::NoCandidates("x".to_string()))
+        );
+        assert_eq!(process_part1(input), "4");
+        assert!(
+            explain(input).contains("  a: not on line 2\n  b: not on line 2\n  c: not on line 1")
+        );
+        let explanation = explain("a b (contains x, y)");
+        assert!(explanation.starts_with("x: unresolved\n  a: still possible\n"));
+    }
+
+    #[test]
+    fn queries() {
+        let input = "mxmxvkd kfcds sqjhc nhms (contains dairy, fish)
+trh fvjkl sbzzf mxmxvkd (contains dairy)
+sqjhc fvjkl (contains soy)
+sqjhc mxmxvkd sbzzf (contains fish)
+trh nhms";
+        let food_list = FoodList::parse(input);
+        assert_eq!(
+            food_list.safe_ingredients().into_iter().collect::<Vec<_>>(),
+            ["kfcds", "nhms", "sbzzf", "trh"]
+        );
+        assert_eq!(
+            food_list.safe_counts().into_iter().collect::<Vec<_>>(),
+            [("kfcds", 1), ("nhms", 2), ("sbzzf", 2), ("trh", 2)]
+        );
+        assert_eq!(food_list.safe_foods(), [5]);
+        assert_eq!(food_list.foods_containing("sqjhc"), [1, 3, 4]);
+        assert!(food_list.foods_containing("egg").is_empty());
+        assert!(food_list.unresolved_allergens().is_empty());
+        assert_eq!(process_part1(input), "7");
+        assert_eq!(process_part2(input), "mxmxvkd,sqjhc,fvjkl");
+
+        let food_list = FoodList::parse("a b (contains x, y)\nc (contains z)");
+        assert_eq!(food_list.unresolved_allergens(), ["x", "y"]);
+        let food_list = FoodList::parse(
+            "a b (contains x, y)\nc (contains z)\nd (contains w)\ne (contains v, w)",
+        );
+        assert_eq!(food_list.unresolved_allergens(), ["w", "x", "y"]);
+        let food_list = FoodList::parse("a (contains x, y)\nb (contains z)");
+        assert_eq!(food_list.unresolved_allergens(), ["x", "y"]);
+    }
 }
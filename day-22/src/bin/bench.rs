@@ -0,0 +1,14 @@
+use answer::{process_part2, process_part2_naive};
+use std::{fs, time::Instant};
+
+fn main() {
+    let file = fs::read_to_string("./input.txt").unwrap();
+    let start = Instant::now();
+    let fast = process_part2(&file);
+    let fast_time = start.elapsed();
+    let start = Instant::now();
+    let naive = process_part2_naive(&file);
+    let naive_time = start.elapsed();
+    assert_eq!(fast, naive);
+    println!("input.txt: hashed and memoised {fast_time:?}, naive {naive_time:?}");
+}
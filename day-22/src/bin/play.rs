@@ -0,0 +1,43 @@
+use answer::{process_with, Recursion, Rules, TieBreak};
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: play [--recursion never|all|qualified] [--tie first|discard|return] [--depth N] [--protect]";
+
+fn usage(message: &str) -> ! {
+    eprintln!("{message}\n{USAGE}");
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let file = fs::read_to_string("./input.txt").unwrap();
+    let value = |flag: &str| {
+        args.iter().position(|arg| arg == flag).map(|i| {
+            args.get(i + 1)
+                .map(String::as_str)
+                .unwrap_or_else(|| usage(&format!("Missing value for {flag}")))
+        })
+    };
+    let mut rules = match value("--recursion") {
+        None | Some("never") => Rules::combat(),
+        Some("all") => Rules::recursive_combat(),
+        Some("qualified") => Rules::recursive_combat().with_recursion(Recursion::QualifiedPlayers),
+        Some(other) => usage(&format!("Unknown recursion condition: {other}")),
+    };
+    rules = match value("--tie") {
+        None | Some("first") => rules,
+        Some("discard") => rules.with_tie_break(TieBreak::Discard),
+        Some("return") => rules.with_tie_break(TieBreak::ReturnCards),
+        Some(other) => usage(&format!("Unknown tie break: {other}")),
+    };
+    if let Some(depth) = value("--depth") {
+        let depth = depth
+            .parse()
+            .unwrap_or_else(|_| usage(&format!("Invalid depth: {depth}")));
+        rules = rules.with_max_depth(depth);
+    }
+    if args.iter().any(|arg| arg == "--protect") {
+        rules = rules.with_repeat_protection();
+    }
+    println!("{}", process_with(&file, &rules));
+}
@@ -0,0 +1,12 @@
+use answer::{transcript_part1, transcript_part2};
+use std::{env, fs};
+
+fn main() {
+    let file = fs::read_to_string("./input.txt").unwrap();
+    let transcript = match env::args().nth(1).as_deref() {
+        Some("1") => transcript_part1(&file),
+        Some("2") | None => transcript_part2(&file),
+        Some(other) => panic!("Unknown part: {other}"),
+    };
+    print!("{transcript}");
+}
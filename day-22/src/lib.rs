@@ -1,4 +1,4 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use nom::{
     bytes::complete::tag,
@@ -10,110 +10,443 @@ use nom::{
 };
 
 pub fn process_part1(input: &str) -> String {
-    let (_, (mut player1, mut player2)) = parse_input(input).unwrap();
-    let mut game_over = false;
-    while !game_over {
-        let (card1, card2) = (player1.pop_front().unwrap(), player2.pop_front().unwrap());
-        match card1.cmp(&card2) {
-            std::cmp::Ordering::Equal => unreachable!(),
-            std::cmp::Ordering::Less => {
-                player2.push_back(card2);
-                player2.push_back(card1);
-            }
-            std::cmp::Ordering::Greater => {
-                player1.push_back(card1);
-                player1.push_back(card2);
-            }
+    process_with(input, &Rules::combat())
+}
+
+pub fn process_part2(input: &str) -> String {
+    process_with(input, &Rules::recursive_combat())
+}
+
+pub fn process_part2_naive(input: &str) -> String {
+    let (_, decks) = parse_input(input).unwrap();
+    let rules = Rules::recursive_combat();
+    outcome_score(Game::naive(&rules).play(decks, 0))
+}
+
+pub fn transcript_part1(input: &str) -> String {
+    transcript_with(input, &Rules::combat())
+}
+
+pub fn transcript_part2(input: &str) -> String {
+    transcript_with(input, &Rules::recursive_combat())
+}
+
+/// What happens when the highest card is drawn by more than one player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The tied player with the lowest number wins the round.
+    FirstPlayer,
+    /// Nobody wins the round and the drawn cards leave the game.
+    Discard,
+    /// Nobody wins the round and everyone puts their card back underneath.
+    /// Implies repeat protection.
+    ReturnCards,
+}
+
+/// When a round is decided by a sub-game instead of the highest card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recursion {
+    Never,
+    /// Every player has at least as many cards left as the value they drew.
+    AllPlayers,
+    /// At least two players have enough cards left; only they play the sub-game.
+    QualifiedPlayers,
+}
+
+/// The rules of a game of Combat for any number of players. The winner of a
+/// round puts their own card underneath first, then the others from high to low.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    tie_break: TieBreak,
+    recursion: Recursion,
+    max_depth: Option<usize>,
+    repeat_protection: bool,
+}
+
+impl Rules {
+    pub fn combat() -> Self {
+        Self {
+            tie_break: TieBreak::FirstPlayer,
+            recursion: Recursion::Never,
+            max_depth: None,
+            repeat_protection: false,
         }
-        game_over = player1.is_empty() || player2.is_empty();
     }
-    let mut winner = match player1.len() {
-        0 => player2,
-        _ => player1,
-    };
-    winner.push_back(0);
-    let score: usize = winner
-        .into_iter()
-        .rev()
-        .enumerate()
-        .map(|(i, v)| i * v)
-        .sum();
-    score.to_string()
+
+    pub fn recursive_combat() -> Self {
+        Self {
+            recursion: Recursion::AllPlayers,
+            repeat_protection: true,
+            ..Self::combat()
+        }
+    }
+
+    /// Returning tied cards can repeat a position forever, so it also turns
+    /// on repeat protection.
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        if tie_break == TieBreak::ReturnCards {
+            self.repeat_protection = true;
+        }
+        self
+    }
+
+    pub fn with_recursion(mut self, recursion: Recursion) -> Self {
+        self.recursion = recursion;
+        self
+    }
+
+    /// Sub-games nested deeper than `max_depth` are decided by the highest card.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// A game that repeats an earlier position is won by the first player still
+    /// in it, like in Recursive Combat.
+    pub fn with_repeat_protection(mut self) -> Self {
+        self.repeat_protection = true;
+        self
+    }
 }
 
-pub fn process_part2(input: &str) -> String {
-    let (_, (player1, player2)) = parse_input(input).unwrap();
-    let (_, mut winner) = recursive_game(player1, player2);
+pub fn process_with(input: &str, rules: &Rules) -> String {
+    let (_, decks) = parse_input(input).unwrap();
+    outcome_score(Game::new(rules, &decks).play(decks, 0))
+}
+
+// Plays every sub-game out, so the game numbers match the puzzle's examples.
+pub fn transcript_with(input: &str, rules: &Rules) -> String {
+    let (_, decks) = parse_input(input).unwrap();
+    let mut game = Game::transcribed(rules);
+    game.play(decks, 0);
+    game.transcript.unwrap().text
+}
+
+fn outcome_score((winner, mut decks): (Option<usize>, Vec<VecDeque<usize>>)) -> String {
+    match winner {
+        Some(winner) => score(decks.swap_remove(winner)).to_string(),
+        None => "NO WINNER".to_string(),
+    }
+}
+
+fn score(mut winner: VecDeque<usize>) -> usize {
     winner.push_back(0);
-    let score: usize = winner
+    winner
         .into_iter()
         .rev()
         .enumerate()
         .map(|(i, v)| i * v)
-        .sum();
-    score.to_string()
-}
-
-fn recursive_game(
-    mut player1: VecDeque<usize>,
-    mut player2: VecDeque<usize>,
-) -> (usize, VecDeque<usize>) {
-    // (winner, winner's deck)
-    let mut game_history: HashSet<(Vec<usize>, Vec<usize>)> = HashSet::new();
-    loop {
-        let game_state = (
-            player1.iter().copied().collect(),
-            player2.iter().copied().collect(),
-        );
-        if game_history.contains(&game_state) {
-            break (1, player1);
-        }
-        game_history.insert(game_state);
-        let (card1, card2) = (player1.pop_front().unwrap(), player2.pop_front().unwrap());
-        if player1.len() >= card1 && player2.len() >= card2 {
-            // play recursive game!
-            let new_player1 = player1.iter().copied().take(card1).collect();
-            let new_player2 = player2.iter().copied().take(card2).collect();
-            let (player, _) = recursive_game(new_player1, new_player2);
-            match player {
-                1 => {
-                    player1.push_back(card1);
-                    player1.push_back(card2);
+        .sum()
+}
+
+// Winners of sub-games by the decks they started with.
+type Memo = HashMap<Vec<VecDeque<usize>>, Option<usize>>;
+
+// Every game and sub-game is played by `Game::play`; the other fields only
+// switch the speedups and the transcript on or off.
+struct Game<'a> {
+    rules: &'a Rules,
+    memo: Option<Memo>,
+    hashed_history: bool,
+    shortcut: bool,
+    transcript: Option<Transcript>,
+}
+
+impl<'a> Game<'a> {
+    fn new(rules: &'a Rules, decks: &[VecDeque<usize>]) -> Self {
+        // Player 1 can never lose their highest card: no round they draw it in
+        // can recurse, because that would need more cards than exist. So if it
+        // beats every card of player 2, player 2 can't win, and since a repeat
+        // is a win for player 1 too, player 1 wins either way. That only holds
+        // for two players with all cards different.
+        let mut cards: Vec<usize> = decks.iter().flatten().copied().collect();
+        let count = cards.len();
+        cards.sort_unstable();
+        cards.dedup();
+        Self {
+            rules,
+            // a sub-game only depends on its decks if the depth doesn't matter
+            memo: rules.max_depth.is_none().then(HashMap::new),
+            hashed_history: true,
+            shortcut: decks.len() == 2
+                && cards.len() == count
+                && rules.recursion != Recursion::Never
+                && rules.repeat_protection,
+            transcript: None,
+        }
+    }
+
+    // Full deck copies in the history and every sub-game played out.
+    fn naive(rules: &'a Rules) -> Self {
+        Self {
+            rules,
+            memo: None,
+            hashed_history: false,
+            shortcut: false,
+            transcript: None,
+        }
+    }
+
+    fn transcribed(rules: &'a Rules) -> Self {
+        Self {
+            transcript: Some(Transcript {
+                recursive: rules.recursion != Recursion::Never,
+                ..Transcript::default()
+            }),
+            ..Self::naive(rules)
+        }
+    }
+
+    // Returns the index of the winner, if anyone is left, and the final decks.
+    fn play(
+        &mut self,
+        decks: Vec<VecDeque<usize>>,
+        depth: usize,
+    ) -> (Option<usize>, Vec<VecDeque<usize>>) {
+        let rules = self.rules;
+        let game = match self.transcript.as_mut() {
+            Some(t) => t.start_game(),
+            None => 0,
+        };
+        let mut decks: Vec<Deck> = decks.into_iter().map(Deck::new).collect();
+        let mut hash_history: HashSet<Vec<u64>> = HashSet::new();
+        let mut deck_history: HashSet<Vec<VecDeque<usize>>> = HashSet::new();
+        let mut round = 0;
+        let winner = loop {
+            let active: Vec<usize> = (0..decks.len())
+                .filter(|&p| !decks[p].cards.is_empty())
+                .collect();
+            if active.len() < 2 {
+                break active.first().copied();
+            }
+            if rules.repeat_protection {
+                let new = if self.hashed_history {
+                    hash_history.insert(decks.iter().map(|deck| deck.hash).collect())
+                } else {
+                    deck_history.insert(decks.iter().map(|deck| deck.cards.clone()).collect())
+                };
+                if !new {
+                    break Some(active[0]);
                 }
-                2 => {
-                    player2.push_back(card2);
-                    player2.push_back(card1);
+            }
+            round += 1;
+            if let Some(t) = self.transcript.as_mut() {
+                t.round(round, game, &decks, &active);
+            }
+            let drawn: Vec<(usize, usize)> = active.iter().map(|&p| (p, decks[p].draw())).collect();
+            if let Some(t) = self.transcript.as_mut() {
+                t.plays(&drawn);
+            }
+            let qualified: Vec<usize> = drawn
+                .iter()
+                .filter(|&&(p, card)| decks[p].cards.len() >= card)
+                .map(|&(p, _)| p)
+                .collect();
+            let recurse = rules.max_depth.is_none_or(|max| depth < max)
+                && match rules.recursion {
+                    Recursion::Never => false,
+                    Recursion::AllPlayers => qualified.len() == drawn.len(),
+                    Recursion::QualifiedPlayers => qualified.len() >= 2,
+                };
+            let sub_game_winner = if recurse {
+                let sub_decks = (0..decks.len())
+                    .map(|p| match drawn.iter().find(|&&(q, _)| q == p) {
+                        Some(&(_, card)) if qualified.contains(&p) => {
+                            decks[p].cards.iter().copied().take(card).collect()
+                        }
+                        _ => VecDeque::new(),
+                    })
+                    .collect();
+                if let Some(t) = self.transcript.as_mut() {
+                    t.line("Playing a sub-game to determine the winner...");
+                    t.line("");
                 }
-                _ => unreachable!(),
+                let winner = self.sub_game(sub_decks, depth + 1);
+                if let Some(t) = self.transcript.as_mut() {
+                    t.line("");
+                    t.line(format!("...anyway, back to game {game}."));
+                }
+                winner
+            } else {
+                None
+            };
+            let winner = sub_game_winner.or_else(|| highest_card(&drawn, rules.tie_break));
+            if let Some(t) = self.transcript.as_mut() {
+                t.round_winner(winner, round, game);
             }
-        } else {
-            // play normal round
-            match card1.cmp(&card2) {
-                std::cmp::Ordering::Equal => unreachable!(),
-                std::cmp::Ordering::Less => {
-                    player2.push_back(card2);
-                    player2.push_back(card1);
+            match winner {
+                Some(winner) => {
+                    let mut cards: Vec<(usize, usize)> = drawn.clone();
+                    cards.sort_by_key(|&(p, card)| (p != winner, std::cmp::Reverse(card)));
+                    cards
+                        .into_iter()
+                        .for_each(|(_, card)| decks[winner].put(card));
                 }
-                std::cmp::Ordering::Greater => {
-                    player1.push_back(card1);
-                    player1.push_back(card2);
+                None if rules.tie_break == TieBreak::ReturnCards => {
+                    drawn.iter().for_each(|&(p, card)| decks[p].put(card));
                 }
+                None => {}
             }
-            if player1.is_empty() {
-                break (2, player2);
-            } else if player2.is_empty() {
-                break (1, player1);
+        };
+        let decks: Vec<VecDeque<usize>> = decks.into_iter().map(|deck| deck.cards).collect();
+        if let Some(t) = self.transcript.as_mut() {
+            t.end_game(winner, game);
+            if depth == 0 {
+                t.results(&decks);
             }
         }
+        (winner, decks)
+    }
+
+    fn sub_game(&mut self, decks: Vec<VecDeque<usize>>, depth: usize) -> Option<usize> {
+        if self.shortcut && decks[0].iter().max() > decks[1].iter().max() {
+            return Some(0);
+        }
+        if let Some(&winner) = self.memo.as_ref().and_then(|memo| memo.get(&decks)) {
+            return winner;
+        }
+        let (winner, _) = self.play(decks.clone(), depth);
+        if let Some(memo) = self.memo.as_mut() {
+            memo.insert(decks, winner);
+        }
+        winner
     }
 }
 
-fn parse_input(input: &str) -> IResult<&str, (VecDeque<usize>, VecDeque<usize>)> {
-    let (input, lines) = separated_list1(pair(newline, newline), parse_player)(input)?;
-    let mut it = lines.into_iter();
-    let player1 = it.next().unwrap();
-    let player2 = it.next().unwrap();
-    Ok((input, (player1, player2)))
+fn highest_card(drawn: &[(usize, usize)], tie_break: TieBreak) -> Option<usize> {
+    let highest = drawn.iter().map(|&(_, card)| card).max()?;
+    let mut tied = drawn.iter().filter(|&&(_, card)| card == highest);
+    let &(first, _) = tied.next()?;
+    match (tied.next(), tie_break) {
+        (None, _) | (Some(_), TieBreak::FirstPlayer) => Some(first),
+        (Some(_), TieBreak::Discard | TieBreak::ReturnCards) => None,
+    }
+}
+
+// The round-by-round log in the format of the puzzle text. Regular Combat
+// leaves out the game numbers.
+#[derive(Debug, Default)]
+struct Transcript {
+    text: String,
+    recursive: bool,
+    games: usize,
+}
+
+impl Transcript {
+    fn line(&mut self, line: impl AsRef<str>) {
+        self.text.push_str(line.as_ref());
+        self.text.push('\n');
+    }
+
+    fn start_game(&mut self) -> usize {
+        self.games += 1;
+        if self.recursive {
+            self.line(format!("=== Game {} ===", self.games));
+            self.line("");
+        }
+        self.games
+    }
+
+    fn round(&mut self, round: usize, game: usize, decks: &[Deck], active: &[usize]) {
+        if round > 1 {
+            self.line("");
+        }
+        if self.recursive {
+            self.line(format!("-- Round {round} (Game {game}) --"));
+        } else {
+            self.line(format!("-- Round {round} --"));
+        }
+        active.iter().for_each(|&p| self.deck(p, &decks[p].cards));
+    }
+
+    fn deck(&mut self, player: usize, deck: &VecDeque<usize>) {
+        let cards: Vec<String> = deck.iter().map(|card| card.to_string()).collect();
+        self.line(format!(
+            "Player {}'s deck: {}",
+            player + 1,
+            cards.join(", ")
+        ));
+    }
+
+    fn plays(&mut self, drawn: &[(usize, usize)]) {
+        drawn
+            .iter()
+            .for_each(|&(p, card)| self.line(format!("Player {} plays: {card}", p + 1)));
+    }
+
+    fn round_winner(&mut self, winner: Option<usize>, round: usize, game: usize) {
+        let who = match winner {
+            Some(winner) => format!("Player {}", winner + 1),
+            None => "Nobody".to_string(),
+        };
+        if self.recursive {
+            self.line(format!("{who} wins round {round} of game {game}!"));
+        } else {
+            self.line(format!("{who} wins the round!"));
+        }
+    }
+
+    fn end_game(&mut self, winner: Option<usize>, game: usize) {
+        if !self.recursive {
+            return;
+        }
+        match winner {
+            Some(winner) => self.line(format!(
+                "The winner of game {game} is player {}!",
+                winner + 1
+            )),
+            None => self.line(format!("Nobody wins game {game}!")),
+        }
+    }
+
+    fn results(&mut self, decks: &[VecDeque<usize>]) {
+        self.line("");
+        self.line("");
+        self.line("== Post-game results ==");
+        decks
+            .iter()
+            .enumerate()
+            .for_each(|(p, deck)| self.deck(p, deck));
+    }
+}
+
+// Polynomial hash of a deck, kept up to date as cards are drawn from the top
+// and put back underneath: the top card carries the highest power of `BASE`.
+// Cards are hashed as their value plus one so a 0 still changes the hash and
+// decks of different lengths can't collide just by leading with zeroes.
+struct Deck {
+    cards: VecDeque<usize>,
+    hash: u64,
+}
+
+const BASE: u64 = 0x100000001b3;
+
+impl Deck {
+    fn new(cards: VecDeque<usize>) -> Self {
+        let hash = cards.iter().fold(0, |hash: u64, &card| {
+            hash.wrapping_mul(BASE).wrapping_add(card as u64 + 1)
+        });
+        Self { cards, hash }
+    }
+
+    fn draw(&mut self) -> usize {
+        let card = self.cards.pop_front().unwrap();
+        let power = BASE.wrapping_pow(self.cards.len() as u32);
+        self.hash = self
+            .hash
+            .wrapping_sub((card as u64 + 1).wrapping_mul(power));
+        card
+    }
+
+    fn put(&mut self, card: usize) {
+        self.hash = self.hash.wrapping_mul(BASE).wrapping_add(card as u64 + 1);
+        self.cards.push_back(card);
+    }
+}
+
+fn parse_input(input: &str) -> IResult<&str, Vec<VecDeque<usize>>> {
+    separated_list1(pair(newline, newline), parse_player)(input)
 }
 
 fn parse_player(input: &str) -> IResult<&str, VecDeque<usize>> {
@@ -164,4 +497,175 @@ Player 2:
         let result = process_part2(input);
         assert_eq!(result, "291");
     }
+
+    #[test]
+    fn rolling_hash() {
+        let mut deck = Deck::new(VecDeque::from([3, 1, 4]));
+        let card = deck.draw();
+        deck.put(card);
+        assert_eq!(deck.hash, Deck::new(VecDeque::from([1, 4, 3])).hash);
+        assert_ne!(deck.hash, Deck::new(VecDeque::from([4, 1, 3])).hash);
+        assert_ne!(
+            Deck::new(VecDeque::from([0, 4])).hash,
+            Deck::new(VecDeque::from([4])).hash
+        );
+    }
+
+    #[test]
+    fn infinite_game() {
+        let input = "Player 1:
+43
+19
+
+Player 2:
+2
+29
+14";
+        let (_, decks) = parse_input(input).unwrap();
+        let rules = Rules::recursive_combat();
+        let (winner, _) = Game::new(&rules, &decks).play(decks, 0);
+        assert_eq!(winner, Some(0));
+        assert_eq!(process_part2(input), process_part2_naive(input));
+
+        // a 0 on top of a deck must not look like a repeat of the shorter deck
+        let input = "Player 1:\n4\n0\n\nPlayer 2:\n0\n1\n3\n4\n4";
+        assert_eq!(process_part2(input), "65");
+        assert_eq!(process_part2_naive(input), "65");
+    }
+
+    #[test]
+    fn transcripts() {
+        let input = "Player 1:
+9
+2
+6
+3
+1
+
+Player 2:
+5
+8
+4
+7
+10";
+        let transcript = transcript_part1(input);
+        assert!(transcript.starts_with(
+            "-- Round 1 --
+Player 1's deck: 9, 2, 6, 3, 1
+Player 2's deck: 5, 8, 4, 7, 10
+Player 1 plays: 9
+Player 2 plays: 5
+Player 1 wins the round!
+
+-- Round 2 --
+"
+        ));
+        assert!(transcript.ends_with(
+            "Player 2 wins the round!
+
+
+== Post-game results ==
+Player 1's deck: 
+Player 2's deck: 3, 2, 10, 6, 8, 5, 9, 4, 7, 1
+"
+        ));
+        assert_eq!(transcript.matches("-- Round").count(), 29);
+
+        let transcript = transcript_part2(input);
+        assert!(transcript.starts_with("=== Game 1 ===\n\n-- Round 1 (Game 1) --\n"));
+        assert!(transcript.contains(
+            "Player 1 plays: 4
+Player 2 plays: 3
+Playing a sub-game to determine the winner...
+
+=== Game 2 ===
+
+-- Round 1 (Game 2) --
+Player 1's deck: 9, 8, 5, 2
+Player 2's deck: 10, 1, 7
+"
+        ));
+        assert!(transcript.contains(
+            "Player 2 wins round 6 of game 2!
+The winner of game 2 is player 2!
+
+...anyway, back to game 1.
+Player 2 wins round 9 of game 1!
+
+-- Round 10 (Game 1) --
+"
+        ));
+        assert!(transcript.ends_with(
+            "The winner of game 1 is player 2!
+
+
+== Post-game results ==
+Player 1's deck: 
+Player 2's deck: 7, 5, 6, 2, 4, 1, 10, 8, 9, 3
+"
+        ));
+    }
+
+    #[test]
+    fn configurable_rules() {
+        let input = "Player 1:
+9
+2
+6
+3
+1
+
+Player 2:
+5
+8
+4
+7
+10";
+        assert_eq!(process_with(input, &Rules::combat()), "306");
+        assert_eq!(process_with(input, &Rules::recursive_combat()), "291");
+        assert_eq!(
+            process_with(input, &Rules::recursive_combat().with_max_depth(0)),
+            "306"
+        );
+
+        let input = "Player 1:\n3\n9\n\nPlayer 2:\n7\n1\n\nPlayer 3:\n5\n2";
+        assert_eq!(process_with(input, &Rules::combat()), "108");
+        assert_eq!(
+            process_with(
+                input,
+                &Rules::recursive_combat().with_recursion(Recursion::QualifiedPlayers)
+            ),
+            process_with(input, &Rules::recursive_combat())
+        );
+
+        let input = "Player 1:\n4\n1\n\nPlayer 2:\n4\n2";
+        assert_eq!(process_with(input, &Rules::combat()), "31");
+        // ties follow the same rules on every path
+        assert_eq!(process_part1(input), "31");
+        assert_eq!(process_part2(input), process_part2_naive(input));
+        assert!(transcript_part1(input).starts_with(
+            "-- Round 1 --
+Player 1's deck: 4, 1
+Player 2's deck: 4, 2
+Player 1 plays: 4
+Player 2 plays: 4
+Player 1 wins the round!
+"
+        ));
+        let duplicates = "Player 1:\n2\n1\n1\n\nPlayer 2:\n2\n1\n1";
+        assert_eq!(process_part2(duplicates), process_part2_naive(duplicates));
+        let discard = Rules::combat().with_tie_break(TieBreak::Discard);
+        assert_eq!(process_with(input, &discard), "5");
+        assert_eq!(
+            process_with("Player 1:\n4\n\nPlayer 2:\n4", &discard),
+            "NO WINNER"
+        );
+
+        // without protection this would go on forever
+        let returning = Rules::combat().with_tie_break(TieBreak::ReturnCards);
+        assert_eq!(
+            process_with("Player 1:\n5\n\nPlayer 2:\n5", &returning),
+            "5"
+        );
+    }
 }
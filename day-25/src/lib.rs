@@ -1,33 +1,117 @@
+use std::{collections::HashMap, fmt::Display};
+
 use nom::{
     character::complete::digit1, character::complete::newline, multi::separated_list1, IResult,
 };
 
 pub fn process_part1(input: &str) -> String {
+    process_handshake(input, 20201227, 7).unwrap_or_else(|e| panic!("{e}"))
+}
+
+pub fn process_part2(_input: &str) -> String {
+    "".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    Modulus(usize),
+    KeyCount(usize),
+    NoLoopSize(usize),
+}
+
+impl Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Modulus(modulus) => write!(f, "Modulus {modulus} has to be at least 2"),
+            Self::KeyCount(count) => write!(f, "Expected two public keys, found {count}"),
+            Self::NoLoopSize(key) => write!(
+                f,
+                "Public key {key} can't be reached from this subject number and modulus"
+            ),
+        }
+    }
+}
+
+pub fn process_handshake(
+    input: &str,
+    modulus: usize,
+    subject_number: usize,
+) -> Result<String, HandshakeError> {
+    if modulus < 2 {
+        return Err(HandshakeError::Modulus(modulus));
+    }
     let (_, keys) = parse_input(input).unwrap();
-    assert_eq!(keys.len(), 2);
-    let card = keys[0];
-    let door = keys[1];
-    let mut n = 1;
-    let mut card_loop = 0;
-    while n != card {
-        n = do_loop(n, 7);
-        card_loop += 1;
+    let [card, door] = keys[..] else {
+        return Err(HandshakeError::KeyCount(keys.len()));
+    };
+    let card_loop =
+        loop_size(card, subject_number, modulus).ok_or(HandshakeError::NoLoopSize(card))?;
+    Ok(mod_pow(door, card_loop, modulus).to_string())
+}
+
+fn loop_size(public_key: usize, subject_number: usize, modulus: usize) -> Option<usize> {
+    // baby-step giant-step: public_key = subject^(i * m + j) for some i, j < m
+    let m = (modulus as f64).sqrt().ceil() as usize;
+    let mut baby_steps = HashMap::with_capacity(m);
+    let mut n = 1 % modulus;
+    for j in 0..m {
+        baby_steps.entry(n).or_insert(j);
+        n = mul_mod(n, subject_number, modulus);
     }
-    n = 1;
-    (0..card_loop).for_each(|_| {
-        n = do_loop(n, door);
-    });
+    let target = public_key % modulus;
+    if let Some(&j) = baby_steps.get(&target) {
+        return Some(j);
+    }
+    let Some(giant_step) = mod_inverse(mod_pow(subject_number, m, modulus), modulus) else {
+        // Without an inverse there are no giant steps, so keep walking one power
+        // at a time: the powers repeat within `modulus` steps, which makes this
+        // O(modulus) for subjects sharing a factor with the modulus.
+        return (m..modulus).find(|_| {
+            let found = n == target;
+            n = mul_mod(n, subject_number, modulus);
+            found
+        });
+    };
+    let mut n = target;
+    for i in 0..m {
+        if let Some(j) = baby_steps.get(&n) {
+            return Some(i * m + j);
+        }
+        n = mul_mod(n, giant_step, modulus);
+    }
+    None
+}
 
-    n.to_string()
+fn mod_pow(mut base: usize, mut exponent: usize, modulus: usize) -> usize {
+    let mut result = 1 % modulus;
+    base %= modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exponent >>= 1;
+    }
+    result
 }
 
-pub fn process_part2(_input: &str) -> String {
-    "".to_string()
+fn mod_inverse(n: usize, modulus: usize) -> Option<usize> {
+    let (mut old_r, mut r) = (n as i128, modulus as i128);
+    let (mut old_s, mut s) = (1_i128, 0_i128);
+    while r != 0 {
+        let q = old_r / r;
+        (old_r, r) = (r, old_r - q * r);
+        (old_s, s) = (s, old_s - q * s);
+    }
+    if old_r != 1 {
+        return None;
+    }
+    Some(old_s.rem_euclid(modulus as i128) as usize)
 }
 
 #[inline(always)]
-fn do_loop(n: usize, subject_number: usize) -> usize {
-    (n * subject_number) % 20201227
+fn mul_mod(a: usize, b: usize, modulus: usize) -> usize {
+    ((a as u128 * b as u128) % modulus as u128) as usize
 }
 
 type Line = usize;
@@ -54,6 +138,48 @@ mod tests {
         assert_eq!(result, "14897079");
     }
 
+    #[test]
+    fn loop_sizes() {
+        assert_eq!(loop_size(5764801, 7, 20201227), Some(8));
+        assert_eq!(loop_size(17807724, 7, 20201227), Some(11));
+        assert_eq!(mod_pow(17807724, 8, 20201227), 14897079);
+        assert_eq!(mod_pow(5764801, 11, 20201227), 14897079);
+    }
+
+    #[test]
+    fn other_parameters() {
+        // 5^3 = 24 and 5^10 = 36 (mod 101)
+        assert_eq!(loop_size(24, 5, 101), Some(3));
+        let input = "24
+36";
+        assert_eq!(
+            process_handshake(input, 101, 5),
+            Ok(mod_pow(5, 30, 101).to_string())
+        );
+        // 2 has no inverse mod 8, but its powers can still be reached
+        assert_eq!(loop_size(2, 2, 8), Some(1));
+        assert_eq!(loop_size(0, 2, 8), Some(3));
+        assert_eq!(loop_size(0, 6, 36), Some(2));
+        assert_eq!(loop_size(3, 2, 8), None);
+        assert_eq!(process_handshake("2\n4", 8, 2), Ok("4".to_string()));
+        assert_eq!(
+            process_handshake("3\n4", 8, 2),
+            Err(HandshakeError::NoLoopSize(3))
+        );
+        assert_eq!(
+            process_handshake("2\n4", 0, 2),
+            Err(HandshakeError::Modulus(0))
+        );
+        assert_eq!(
+            process_handshake("0\n0", 1, 2),
+            Err(HandshakeError::Modulus(1))
+        );
+        assert_eq!(
+            process_handshake("24", 101, 5),
+            Err(HandshakeError::KeyCount(1))
+        );
+    }
+
     #[test]
     fn part2() {
         let input = "";